@@ -0,0 +1,80 @@
+//! Bridges expose a managed device to clients that don't speak gRPC.
+//! Every bridge subscribes to the outbound data and feeds the inbound channel of the device.
+pub mod rfc2217;
+pub mod tcp;
+pub mod udp;
+
+use rand::Rng;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::{debug, error, info};
+
+/// How many random ports to try before giving up
+const BIND_ATTEMPTS: usize = 16;
+
+/// Bind to `port`, or to a random port in `range` if it's 0, retrying while the port is in use
+pub async fn bind_in_range<T, F, Fut>(
+    ip: IpAddr,
+    port: u16,
+    range: &RangeInclusive<u16>,
+    bind: F,
+) -> io::Result<T>
+where
+    F: Fn(SocketAddr) -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    if port != 0 {
+        return bind(SocketAddr::new(ip, port)).await;
+    }
+    let mut last_err = io::Error::new(io::ErrorKind::AddrInUse, "no port available");
+    for _ in 0..BIND_ATTEMPTS {
+        // https://stackoverflow.com/questions/67443847/how-to-generate-random-numbers-in-async-rust
+        let port = rand::thread_rng().gen_range(range.clone());
+        match bind(SocketAddr::new(ip, port)).await {
+            Ok(s) => return Ok(s),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                debug!("port {} is in use, retrying", port);
+                last_err = e;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_err)
+}
+
+/// Accept connections on `listener` and serve each of them in its own task.
+/// Aborting the returned task aborts those of the connections too.
+pub fn accept_loop<F, Fut>(listener: TcpListener, name: &'static str, serve: F) -> JoinHandle<()>
+where
+    F: Fn(TcpStream) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        // dropped along with this task, which aborts whatever is left in it
+        let mut connections = JoinSet::new();
+        loop {
+            tokio::select! {
+                r = listener.accept() => match r {
+                    Ok((stream, peer)) => {
+                        info!("{} peer {} connected", name, peer);
+                        let _ = stream.set_nodelay(true);
+                        let fut = serve(stream);
+                        connections.spawn(async move {
+                            fut.await;
+                            info!("{} peer {} disconnected", name, peer);
+                        });
+                    }
+                    Err(e) => {
+                        error!("error accepting {} connection: {}", name, e);
+                    }
+                },
+                // reap the finished ones
+                Some(_) = connections.join_next() => {}
+            }
+        }
+    })
+}
@@ -0,0 +1,611 @@
+//! RFC 2217 (Telnet COM port control) server
+//! https://datatracker.ietf.org/doc/html/rfc2217
+//! https://github.com/pyserial/pyserial/blob/master/serial/rfc2217.py
+use crate::modem;
+use crate::serial::api::out as api;
+use crate::server::{PortControl, Subscribers, WriteCommand};
+use flume::Sender;
+use serialport::{ClearBuffer, SerialPort};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_stream::StreamExt;
+use tracing::{debug, error, warn};
+
+// https://datatracker.ietf.org/doc/html/rfc854
+const SE: u8 = 240;
+const SB: u8 = 250;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+const IAC: u8 = 255;
+
+const BINARY: u8 = 0;
+const SGA: u8 = 3;
+const COM_PORT_OPTION: u8 = 44;
+
+// commands from the client. The server answers with the same command + SERVER_OFFSET
+const SIGNATURE: u8 = 0;
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const NOTIFY_LINESTATE: u8 = 6;
+const NOTIFY_MODEMSTATE: u8 = 7;
+const FLOWCONTROL_SUSPEND: u8 = 8;
+const FLOWCONTROL_RESUME: u8 = 9;
+const SET_LINESTATE_MASK: u8 = 10;
+const SET_MODEMSTATE_MASK: u8 = 11;
+const PURGE_DATA: u8 = 12;
+const SERVER_OFFSET: u8 = 100;
+
+// values of SET_CONTROL
+const CONTROL_REQ_FLOW: u8 = 0;
+const CONTROL_NO_FLOW: u8 = 1;
+const CONTROL_SW_FLOW: u8 = 2;
+const CONTROL_HW_FLOW: u8 = 3;
+const CONTROL_REQ_BREAK: u8 = 4;
+const CONTROL_BREAK_ON: u8 = 5;
+const CONTROL_BREAK_OFF: u8 = 6;
+const CONTROL_REQ_DTR: u8 = 7;
+const CONTROL_DTR_ON: u8 = 8;
+const CONTROL_DTR_OFF: u8 = 9;
+const CONTROL_REQ_RTS: u8 = 10;
+const CONTROL_RTS_ON: u8 = 11;
+const CONTROL_RTS_OFF: u8 = 12;
+
+// bits of NOTIFY_MODEMSTATE
+const MODEMSTATE_CD: u8 = 0x80;
+const MODEMSTATE_RI: u8 = 0x40;
+const MODEMSTATE_DSR: u8 = 0x20;
+const MODEMSTATE_CTS: u8 = 0x10;
+/// delta CD, trailing edge RI, delta DSR and delta CTS
+const MODEMSTATE_DELTA: u8 = 0x0f;
+
+// values of PURGE_DATA
+const PURGE_RX: u8 = 1;
+const PURGE_TX: u8 = 2;
+const PURGE_BOTH: u8 = 3;
+
+const SERVER_SIGNATURE: &[u8] = b"serial-monitor-rs";
+
+/// Accept RFC 2217 clients. Each of them could read/write the data
+/// and change the settings of the serial port.
+pub fn spawn(
+    listener: TcpListener,
+    control: PortControl,
+    outbound: Arc<Subscribers>,
+    modem: modem::Watcher,
+    inbound: Arc<Sender<WriteCommand>>,
+) -> JoinHandle<()> {
+    super::accept_loop(listener, "RFC 2217", move |stream| {
+        let session = Session::new(control.clone(), inbound.clone());
+        let outbound = outbound.clone();
+        let modem_events = modem.subscribe();
+        async move { serve(stream, session, &outbound, modem_events).await }
+    })
+}
+
+async fn serve(
+    stream: TcpStream,
+    mut session: Session,
+    outbound: &Arc<Subscribers>,
+    mut modem_events: broadcast::Receiver<api::ModemLinesEvent>,
+) {
+    let mut subscription = outbound.subscribe();
+    let (mut rx, mut tx) = stream.into_split();
+    let mut buf = [0u8; 4096];
+    session.start();
+    loop {
+        if !session.reply.is_empty() {
+            let reply = std::mem::take(&mut session.reply);
+            if let Err(e) = tx.write_all(&reply).await {
+                debug!("error writing to RFC 2217 peer: {}", e);
+                break;
+            }
+        }
+        tokio::select! {
+            r = rx.read(&mut buf) => match r {
+                Ok(0) => break,
+                Ok(n) => {
+                    if session.feed(&buf[..n]).await.is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    debug!("error reading from RFC 2217 peer: {}", e);
+                    break;
+                }
+            },
+            // stop polling while the client asked us to hold on
+            chunk = subscription.next(), if !session.suspended => match chunk {
+                Some(chunk) => {
+                    if let Err(e) = tx.write_all(&escape(&chunk.data)).await {
+                        debug!("error writing to RFC 2217 peer: {}", e);
+                        break;
+                    }
+                }
+                // the device is closed
+                None => break,
+            },
+            e = modem_events.recv() => match e {
+                Ok(e) => session.notify_modem_state(&e),
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+}
+
+/// Double every IAC so the data isn't taken for a command
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut v = Vec::with_capacity(data.len());
+    for &b in data {
+        v.push(b);
+        if b == IAC {
+            v.push(IAC);
+        }
+    }
+    v
+}
+
+enum ParseState {
+    Data,
+    Iac,
+    Negotiation(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// The inbound channel is gone, i.e. the device is closed
+struct Closed;
+
+struct Session {
+    control: PortControl,
+    inbound: Arc<Sender<WriteCommand>>,
+    state: ParseState,
+    /// options enabled on our side (WILL) and on the client side (DO)
+    local: HashSet<u8>,
+    remote: HashSet<u8>,
+    subnegotiation: Vec<u8>,
+    /// data to be written to the serial port
+    data: Vec<u8>,
+    /// bytes to be sent to the client
+    reply: Vec<u8>,
+    /// the client asked us to stop sending data (FLOWCONTROL-SUSPEND)
+    suspended: bool,
+    /// which bits of NOTIFY-MODEMSTATE the client wants to be notified of
+    modemstate_mask: u8,
+}
+
+impl Session {
+    fn new(control: PortControl, inbound: Arc<Sender<WriteCommand>>) -> Self {
+        Session {
+            control,
+            inbound,
+            state: ParseState::Data,
+            local: HashSet::new(),
+            remote: HashSet::new(),
+            subnegotiation: Vec::new(),
+            data: Vec::new(),
+            reply: Vec::new(),
+            suspended: false,
+            modemstate_mask: 0xff,
+        }
+    }
+
+    /// Offer the options we'd like to use
+    fn start(&mut self) {
+        for option in [BINARY, SGA, COM_PORT_OPTION] {
+            self.local.insert(option);
+            self.send_command(WILL, option);
+        }
+        for option in [BINARY, SGA] {
+            self.remote.insert(option);
+            self.send_command(DO, option);
+        }
+    }
+
+    fn send_command(&mut self, verb: u8, option: u8) {
+        self.reply.extend_from_slice(&[IAC, verb, option]);
+    }
+
+    fn send_subnegotiation(&mut self, command: u8, value: &[u8]) {
+        self.reply
+            .extend_from_slice(&[IAC, SB, COM_PORT_OPTION, command + SERVER_OFFSET]);
+        self.reply.extend_from_slice(&escape(value));
+        self.reply.extend_from_slice(&[IAC, SE]);
+    }
+
+    async fn feed(&mut self, bytes: &[u8]) -> Result<(), Closed> {
+        for &b in bytes {
+            self.state = match self.state {
+                ParseState::Data if b == IAC => ParseState::Iac,
+                ParseState::Data => {
+                    self.data.push(b);
+                    ParseState::Data
+                }
+                ParseState::Iac => match b {
+                    IAC => {
+                        self.data.push(IAC);
+                        ParseState::Data
+                    }
+                    WILL | WONT | DO | DONT => ParseState::Negotiation(b),
+                    SB => {
+                        self.subnegotiation.clear();
+                        ParseState::Subnegotiation
+                    }
+                    // NOP, GA and the like
+                    _ => ParseState::Data,
+                },
+                ParseState::Negotiation(verb) => {
+                    self.negotiate(verb, b);
+                    ParseState::Data
+                }
+                ParseState::Subnegotiation if b == IAC => ParseState::SubnegotiationIac,
+                ParseState::Subnegotiation => {
+                    self.subnegotiation.push(b);
+                    ParseState::Subnegotiation
+                }
+                ParseState::SubnegotiationIac => match b {
+                    IAC => {
+                        self.subnegotiation.push(IAC);
+                        ParseState::Subnegotiation
+                    }
+                    SE => {
+                        // the data sent before the command should go out with the old settings
+                        self.flush_data().await?;
+                        let sub = std::mem::take(&mut self.subnegotiation);
+                        self.subnegotiate(&sub).await;
+                        ParseState::Data
+                    }
+                    _ => {
+                        warn!("malformed subnegotiation from RFC 2217 peer");
+                        ParseState::Data
+                    }
+                },
+            };
+        }
+        self.flush_data().await
+    }
+
+    async fn flush_data(&mut self) -> Result<(), Closed> {
+        if self.data.is_empty() {
+            return Ok(());
+        }
+        let data = std::mem::take(&mut self.data);
+        self.inbound
+            .send_async(WriteCommand::new(data))
+            .await
+            .map_err(|_| Closed)
+    }
+
+    /// https://datatracker.ietf.org/doc/html/rfc1143
+    /// Only answer requests that change the state, so that we never loop
+    fn negotiate(&mut self, verb: u8, option: u8) {
+        let supported = matches!(option, BINARY | SGA | COM_PORT_OPTION);
+        match verb {
+            DO if !supported => self.send_command(WONT, option),
+            DO if self.local.insert(option) => self.send_command(WILL, option),
+            DONT if self.local.remove(&option) => self.send_command(WONT, option),
+            WILL if !supported => self.send_command(DONT, option),
+            WILL if self.remote.insert(option) => self.send_command(DO, option),
+            WONT if self.remote.remove(&option) => self.send_command(DONT, option),
+            _ => {}
+        }
+    }
+
+    async fn subnegotiate(&mut self, sub: &[u8]) {
+        let [COM_PORT_OPTION, command, value @ ..] = sub else {
+            debug!("ignoring subnegotiation {:?}", sub);
+            return;
+        };
+        let command = *command;
+        match command {
+            SIGNATURE => {
+                if value.is_empty() {
+                    self.send_subnegotiation(SIGNATURE, SERVER_SIGNATURE);
+                } else {
+                    debug!("RFC 2217 client: {}", String::from_utf8_lossy(value));
+                }
+            }
+            SET_BAUDRATE => {
+                if let Ok(baud) = <[u8; 4]>::try_from(value) {
+                    let baud = u32::from_be_bytes(baud);
+                    // 0 asks for the current value
+                    if baud != 0 {
+                        let mut options = self.control.options();
+                        options.baud = baud;
+                        self.configure(options).await;
+                    }
+                }
+                let baud = self.control.options().baud;
+                self.send_subnegotiation(command, &baud.to_be_bytes());
+            }
+            SET_DATASIZE => {
+                if let Some(data_bits) = value.first().and_then(|&v| rfc_2_data_bits(v)) {
+                    let mut options = self.control.options();
+                    options.data_bits = data_bits as i32;
+                    self.configure(options).await;
+                }
+                let v = data_bits_2_rfc(self.control.options().data_bits);
+                self.send_subnegotiation(command, &[v]);
+            }
+            SET_PARITY => {
+                if let Some(parity) = value.first().and_then(|&v| rfc_2_parity(v)) {
+                    let mut options = self.control.options();
+                    options.parity = parity as i32;
+                    self.configure(options).await;
+                }
+                let v = parity_2_rfc(self.control.options().parity);
+                self.send_subnegotiation(command, &[v]);
+            }
+            SET_STOPSIZE => {
+                if let Some(stop_bits) = value.first().and_then(|&v| rfc_2_stop_bits(v)) {
+                    let mut options = self.control.options();
+                    options.stop_bits = stop_bits as i32;
+                    self.configure(options).await;
+                }
+                let v = stop_bits_2_rfc(self.control.options().stop_bits);
+                self.send_subnegotiation(command, &[v]);
+            }
+            SET_CONTROL => {
+                if let Some(&v) = value.first() {
+                    self.set_control(v).await;
+                }
+            }
+            NOTIFY_LINESTATE => {
+                // we don't track overrun/parity/framing errors
+                self.send_subnegotiation(command, &[0]);
+            }
+            NOTIFY_MODEMSTATE => {
+                let state = self.modem_state().await;
+                self.send_subnegotiation(command, &[state]);
+            }
+            FLOWCONTROL_SUSPEND => self.suspended = true,
+            FLOWCONTROL_RESUME => self.suspended = false,
+            SET_MODEMSTATE_MASK => {
+                if let Some(&mask) = value.first() {
+                    self.modemstate_mask = mask;
+                }
+                self.send_subnegotiation(command, value);
+            }
+            // the line state is never notified, so the mask is simply acknowledged
+            SET_LINESTATE_MASK => {
+                self.send_subnegotiation(command, value);
+            }
+            PURGE_DATA => {
+                let buffer = match value.first() {
+                    Some(&PURGE_RX) => Some(ClearBuffer::Input),
+                    Some(&PURGE_TX) => Some(ClearBuffer::Output),
+                    Some(&PURGE_BOTH) => Some(ClearBuffer::All),
+                    _ => None,
+                };
+                if let Some(buffer) = buffer {
+                    if self.leased() {
+                        debug!("not purging a leased port");
+                    } else if let Err(e) = self.control.with_port(|p| p.clear(buffer)).await {
+                        error!("error purging serial port: {}", e);
+                    }
+                    self.send_subnegotiation(command, value);
+                }
+            }
+            _ => debug!("unsupported RFC 2217 command {}", command),
+        }
+    }
+
+    /// Nothing can be changed while the device is leased,
+    /// the client gets the current settings back instead
+    fn leased(&self) -> bool {
+        self.control.lease().check(None).is_err()
+    }
+
+    async fn configure(&self, options: api::OpenOptions) {
+        if self.leased() {
+            debug!("not configuring a leased port");
+            return;
+        }
+        if let Err(e) = self.control.configure(&options).await {
+            // the client would see the old value in the answer
+            error!("error configuring serial port: {}", e);
+        }
+    }
+
+    async fn set_control(&mut self, value: u8) {
+        let value = if self.leased() {
+            control_request(value)
+        } else {
+            value
+        };
+        let reply = match value {
+            CONTROL_REQ_FLOW => flow_2_rfc(self.control.options().flow_control),
+            CONTROL_NO_FLOW | CONTROL_SW_FLOW | CONTROL_HW_FLOW => {
+                let mut options = self.control.options();
+                options.flow_control = match value {
+                    CONTROL_SW_FLOW => api::FlowControl::Software,
+                    CONTROL_HW_FLOW => api::FlowControl::Hardware,
+                    _ => api::FlowControl::NoFlowControl,
+                } as i32;
+                self.configure(options).await;
+                flow_2_rfc(self.control.options().flow_control)
+            }
+            CONTROL_BREAK_ON | CONTROL_BREAK_OFF => {
+                let on = value == CONTROL_BREAK_ON;
+                if let Err(e) = self.control.set_break(on).await {
+                    error!("error setting break: {}", e);
+                }
+                on_off(
+                    self.control.output_lines().break_on,
+                    CONTROL_BREAK_ON,
+                    CONTROL_BREAK_OFF,
+                )
+            }
+            CONTROL_REQ_BREAK => on_off(
+                self.control.output_lines().break_on,
+                CONTROL_BREAK_ON,
+                CONTROL_BREAK_OFF,
+            ),
+            CONTROL_DTR_ON | CONTROL_DTR_OFF => {
+                let on = value == CONTROL_DTR_ON;
+                if let Err(e) = self.control.set_modem_lines(Some(on), None).await {
+                    error!("error setting DTR: {}", e);
+                }
+                on_off(
+                    self.control.output_lines().dtr,
+                    CONTROL_DTR_ON,
+                    CONTROL_DTR_OFF,
+                )
+            }
+            CONTROL_REQ_DTR => on_off(
+                self.control.output_lines().dtr,
+                CONTROL_DTR_ON,
+                CONTROL_DTR_OFF,
+            ),
+            CONTROL_RTS_ON | CONTROL_RTS_OFF => {
+                let on = value == CONTROL_RTS_ON;
+                if let Err(e) = self.control.set_modem_lines(None, Some(on)).await {
+                    error!("error setting RTS: {}", e);
+                }
+                on_off(
+                    self.control.output_lines().rts,
+                    CONTROL_RTS_ON,
+                    CONTROL_RTS_OFF,
+                )
+            }
+            CONTROL_REQ_RTS => on_off(
+                self.control.output_lines().rts,
+                CONTROL_RTS_ON,
+                CONTROL_RTS_OFF,
+            ),
+            _ => {
+                debug!("unsupported RFC 2217 control {}", value);
+                return;
+            }
+        };
+        self.send_subnegotiation(SET_CONTROL, &[reply]);
+    }
+
+    /// Tell the client about a change of the modem lines, unless it's masked out
+    fn notify_modem_state(&mut self, event: &api::ModemLinesEvent) {
+        let (Some(lines), Some(changed)) = (&event.lines, &event.changed) else {
+            return;
+        };
+        // the delta bits are the lower nibble
+        let state = modem_lines_2_rfc(lines) | (modem_lines_2_rfc(changed) >> 4);
+        if state & self.modemstate_mask & MODEMSTATE_DELTA != 0 {
+            self.send_subnegotiation(NOTIFY_MODEMSTATE, &[state & self.modemstate_mask]);
+        }
+    }
+
+    async fn modem_state(&self) -> u8 {
+        match self.control.modem_lines().await {
+            Ok(lines) => modem_lines_2_rfc(&lines),
+            Err(e) => {
+                error!("error reading modem lines: {}", e);
+                0
+            }
+        }
+    }
+}
+
+fn modem_lines_2_rfc(lines: &api::ModemLines) -> u8 {
+    let mut state = 0;
+    if lines.cd {
+        state |= MODEMSTATE_CD;
+    }
+    if lines.ri {
+        state |= MODEMSTATE_RI;
+    }
+    if lines.dsr {
+        state |= MODEMSTATE_DSR;
+    }
+    if lines.cts {
+        state |= MODEMSTATE_CTS;
+    }
+    state
+}
+
+/// The SET-CONTROL value asking for the current state of what `value` would change
+fn control_request(value: u8) -> u8 {
+    match value {
+        CONTROL_NO_FLOW | CONTROL_SW_FLOW | CONTROL_HW_FLOW => CONTROL_REQ_FLOW,
+        CONTROL_BREAK_ON | CONTROL_BREAK_OFF => CONTROL_REQ_BREAK,
+        CONTROL_DTR_ON | CONTROL_DTR_OFF => CONTROL_REQ_DTR,
+        CONTROL_RTS_ON | CONTROL_RTS_OFF => CONTROL_REQ_RTS,
+        _ => value,
+    }
+}
+
+fn on_off(on: bool, if_on: u8, if_off: u8) -> u8 {
+    if on {
+        if_on
+    } else {
+        if_off
+    }
+}
+
+fn rfc_2_data_bits(v: u8) -> Option<api::DataBits> {
+    match v {
+        5 => Some(api::DataBits::Five),
+        6 => Some(api::DataBits::Six),
+        7 => Some(api::DataBits::Seven),
+        8 => Some(api::DataBits::Eight),
+        _ => None,
+    }
+}
+
+fn data_bits_2_rfc(data_bits: i32) -> u8 {
+    match api::DataBits::try_from(data_bits) {
+        Ok(api::DataBits::Five) => 5,
+        Ok(api::DataBits::Six) => 6,
+        Ok(api::DataBits::Seven) => 7,
+        _ => 8,
+    }
+}
+
+/// MARK (4) and SPACE (5) are not supported by serialport
+fn rfc_2_parity(v: u8) -> Option<api::Parity> {
+    match v {
+        1 => Some(api::Parity::NoParity),
+        2 => Some(api::Parity::Odd),
+        3 => Some(api::Parity::Even),
+        _ => None,
+    }
+}
+
+fn parity_2_rfc(parity: i32) -> u8 {
+    match api::Parity::try_from(parity) {
+        Ok(api::Parity::Odd) => 2,
+        Ok(api::Parity::Even) => 3,
+        _ => 1,
+    }
+}
+
+/// 1.5 stop bits (3) is not supported by serialport
+fn rfc_2_stop_bits(v: u8) -> Option<api::StopBits> {
+    match v {
+        1 => Some(api::StopBits::One),
+        2 => Some(api::StopBits::Two),
+        _ => None,
+    }
+}
+
+fn stop_bits_2_rfc(stop_bits: i32) -> u8 {
+    match api::StopBits::try_from(stop_bits) {
+        Ok(api::StopBits::Two) => 2,
+        _ => 1,
+    }
+}
+
+fn flow_2_rfc(flow: i32) -> u8 {
+    match api::FlowControl::try_from(flow) {
+        Ok(api::FlowControl::Software) => CONTROL_SW_FLOW,
+        Ok(api::FlowControl::Hardware) => CONTROL_HW_FLOW,
+        _ => CONTROL_NO_FLOW,
+    }
+}
@@ -0,0 +1,54 @@
+use crate::server::{Subscribers, WriteCommand};
+use flume::Sender;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_stream::StreamExt;
+use tracing::debug;
+
+/// Accept raw TCP connections (like `nc`, `socat` or pyserial's `socket://`)
+/// and forward bytes between each of them and the serial port.
+pub fn spawn(
+    listener: TcpListener,
+    outbound: Arc<Subscribers>,
+    inbound: Arc<Sender<WriteCommand>>,
+) -> JoinHandle<()> {
+    super::accept_loop(listener, "TCP", move |stream| {
+        let outbound = outbound.clone();
+        let inbound = inbound.clone();
+        async move { serve(stream, &outbound, &inbound).await }
+    })
+}
+
+async fn serve(stream: TcpStream, outbound: &Arc<Subscribers>, inbound: &Sender<WriteCommand>) {
+    let mut subscription = outbound.subscribe();
+    let (mut rx, mut tx) = stream.into_split();
+    let mut buf = [0u8; 4096];
+    loop {
+        tokio::select! {
+            r = rx.read(&mut buf) => match r {
+                Ok(0) => break,
+                Ok(n) => {
+                    if inbound.send_async(WriteCommand::new(buf[..n].to_vec())).await.is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    debug!("error reading from TCP peer: {}", e);
+                    break;
+                }
+            },
+            chunk = subscription.next() => match chunk {
+                Some(chunk) => {
+                    if let Err(e) = tx.write_all(&chunk.data).await {
+                        debug!("error writing to TCP peer: {}", e);
+                        break;
+                    }
+                }
+                // the device is closed
+                None => break,
+            },
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::server::{Subscription, WriteCommand};
+use flume::Sender;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tokio_stream::StreamExt;
+use tracing::{debug, error, warn};
+
+/// A peer keeps receiving the serial output for this long after its last datagram.
+/// Send an empty datagram to keep alive without writing anything to the port.
+const PEER_TTL: Duration = Duration::from_secs(60);
+
+const MAX_DATAGRAM_SIZE: usize = 65507;
+
+/// The peers of a UDP bridge by the last time they were heard of
+#[derive(Default, Clone)]
+pub struct Peers(Arc<parking_lot::Mutex<HashMap<SocketAddr, Instant>>>);
+
+impl Peers {
+    /// Whether the peer is new
+    fn seen(&self, peer: SocketAddr) -> bool {
+        self.0.lock().insert(peer, Instant::now()).is_none()
+    }
+    /// Forget the expired peers and return the others
+    fn live(&self) -> Vec<SocketAddr> {
+        let mut peers = self.0.lock();
+        peers.retain(|peer, last_seen| {
+            let alive = last_seen.elapsed() < PEER_TTL;
+            if !alive {
+                debug!("UDP peer {} expired", peer);
+            }
+            alive
+        });
+        peers.keys().copied().collect()
+    }
+    pub fn count(&self) -> usize {
+        let peers = self.0.lock();
+        peers.values().filter(|t| t.elapsed() < PEER_TTL).count()
+    }
+}
+
+/// Pump datagrams received on `socket` into the serial port,
+/// and send the serial output back to every peer that has recently talked to it.
+pub fn spawn(
+    socket: Arc<UdpSocket>,
+    peers: Peers,
+    mut outbound: Subscription,
+    inbound: Arc<Sender<WriteCommand>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+        loop {
+            tokio::select! {
+                r = socket.recv_from(&mut buf) => match r {
+                    Ok((n, peer)) => {
+                        if peers.seen(peer) {
+                            debug!("new UDP peer {}", peer);
+                        }
+                        if n == 0 {
+                            continue;
+                        }
+                        if inbound.send_async(WriteCommand::new(buf[..n].to_vec())).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        // e.g. ICMP port unreachable from a peer that has gone away
+                        warn!("error receiving from UDP socket: {}", e);
+                    }
+                },
+                chunk = outbound.next() => match chunk {
+                    Some(chunk) => {
+                        for peer in peers.live() {
+                            if let Err(e) = socket.send_to(&chunk.data, peer).await {
+                                error!("error sending to UDP peer {}: {}", peer, e);
+                            }
+                        }
+                    }
+                    // the device is closed
+                    None => break,
+                },
+            }
+        }
+    })
+}
@@ -0,0 +1,147 @@
+use crate::selector::Selector;
+use clap::Parser;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Expose serial ports over gRPC")]
+pub struct Args {
+    /// Address to listen on
+    #[arg(short, long)]
+    pub address: Option<IpAddr>,
+    /// Port to listen on
+    #[arg(short, long)]
+    pub port: Option<u16>,
+    /// One of trace, debug, info, warn, error
+    #[arg(short, long)]
+    pub log_level: Option<tracing::Level>,
+    /// Path to a TOML config file. Command line arguments take precedence over it
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Serve Prometheus metrics on http://<address>/metrics, e.g. 0.0.0.0:9898
+    #[arg(long)]
+    pub metrics_address: Option<SocketAddr>,
+}
+
+/// Server-wide settings, read from the config file
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub address: IpAddr,
+    pub port: u16,
+    pub log_level: String,
+    /// Where to serve the Prometheus metrics, not served if unset
+    pub metrics_address: Option<SocketAddr>,
+    pub udp: BridgeConfig,
+    pub tcp: BridgeConfig,
+    pub rfc2217: BridgeConfig,
+    /// Names for the ports to open by selector, e.g.
+    /// `[aliases.board1]` then `vid = 0x0403` and `serial_number = "A50285BI"`
+    pub aliases: HashMap<String, Selector>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 50051,
+            log_level: "info".to_string(),
+            metrics_address: None,
+            udp: BridgeConfig::default(),
+            tcp: BridgeConfig::default(),
+            rfc2217: BridgeConfig::default(),
+            aliases: HashMap::new(),
+        }
+    }
+}
+
+/// Defaults for the UDP socket (`[udp]`) or the TCP listeners (`[tcp]`, `[rfc2217]`)
+/// of each managed device
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BridgeConfig {
+    /// Defaults to the address the gRPC server listens on
+    pub bind_address: Option<IpAddr>,
+    /// Ports picked for the devices that don't ask for a specific one,
+    /// e.g. `port_range = { start = 49152, end = 65535 }`
+    pub port_range: RangeInclusive<u16>,
+}
+
+impl Default for BridgeConfig {
+    fn default() -> Self {
+        BridgeConfig {
+            bind_address: None,
+            // https://en.wikipedia.org/wiki/Registered_port
+            port_range: 49152..=65535,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// Read the config file (if any) and override it with the command line arguments
+    pub fn load(args: &Args) -> anyhow::Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => Config::from_file(path)
+                .map_err(|e| anyhow::anyhow!("failed to load {}: {}", path.display(), e))?,
+            None => Config::default(),
+        };
+        if let Some(address) = args.address {
+            config.address = address;
+        }
+        if let Some(port) = args.port {
+            config.port = port;
+        }
+        if let Some(level) = args.log_level {
+            config.log_level = level.to_string();
+        }
+        if let Some(address) = args.metrics_address {
+            config.metrics_address = Some(address);
+        }
+        if config.udp.port_range.is_empty() {
+            anyhow::bail!("invalid UDP port range {:?}", config.udp.port_range);
+        }
+        if config.tcp.port_range.is_empty() {
+            anyhow::bail!("invalid TCP port range {:?}", config.tcp.port_range);
+        }
+        if config.rfc2217.port_range.is_empty() {
+            anyhow::bail!(
+                "invalid RFC 2217 port range {:?}",
+                config.rfc2217.port_range
+            );
+        }
+        if let Some((name, _)) = config.aliases.iter().find(|(_, s)| s.is_empty()) {
+            anyhow::bail!("alias {} doesn't have any criteria", name);
+        }
+        Ok(config)
+    }
+
+    pub fn listen_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.address, self.port)
+    }
+
+    pub fn udp_bind_address(&self) -> IpAddr {
+        self.udp.bind_address.unwrap_or(self.address)
+    }
+
+    pub fn tcp_bind_address(&self) -> IpAddr {
+        self.tcp.bind_address.unwrap_or(self.address)
+    }
+
+    pub fn rfc2217_bind_address(&self) -> IpAddr {
+        self.rfc2217.bind_address.unwrap_or(self.address)
+    }
+
+    pub fn log_level(&self) -> anyhow::Result<tracing::Level> {
+        self.log_level
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid log level {}", self.log_level))
+    }
+}
@@ -0,0 +1,252 @@
+//! Line framing of the Read streams, so that every message is exactly one line
+use crate::serial::api::out as api;
+use crate::server::{api_count_2_usize, api_positive_duration_2_std, Chunk, Subscription};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+use tokio::time::Sleep;
+use tokio_stream::Stream;
+use tracing::debug;
+
+/// Unless [api::LineFraming] says otherwise
+const DEFAULT_DELIMITER: &[u8] = b"\n";
+const DEFAULT_MAX_LENGTH: usize = 4096;
+const MAX_MAX_LENGTH: u32 = 1 << 20;
+
+pub struct LineFraming {
+    delimiter: Vec<u8>,
+    max_length: usize,
+    flush_timeout: Option<Duration>,
+}
+
+impl LineFraming {
+    pub fn from_api(framing: &api::LineFraming) -> Result<Self, tonic::Status> {
+        let delimiter = if framing.delimiter.is_empty() {
+            DEFAULT_DELIMITER.to_vec()
+        } else {
+            framing.delimiter.clone()
+        };
+        let max_length = api_count_2_usize(
+            framing.max_length,
+            DEFAULT_MAX_LENGTH,
+            MAX_MAX_LENGTH,
+            "max line length",
+        )?;
+        if delimiter.len() > max_length {
+            return Err(tonic::Status::invalid_argument(
+                "the delimiter is longer than the max line length",
+            ));
+        }
+        let flush_timeout = api_positive_duration_2_std(&framing.flush_timeout, "flush timeout")?;
+        Ok(LineFraming {
+            delimiter,
+            max_length,
+            flush_timeout,
+        })
+    }
+}
+
+/// The lines of a [Subscription], delimiter included.
+/// Each of them gets the seq and time of the chunk that completes it.
+pub struct Lines {
+    subscription: Subscription,
+    framing: LineFraming,
+    /// the line being received
+    partial: Vec<u8>,
+    /// seq and time of the last chunk
+    last: (u64, SystemTime),
+    /// fires when the partial line has waited long enough for its delimiter
+    flush: Option<Pin<Box<Sleep>>>,
+    ready: VecDeque<Chunk>,
+    ended: bool,
+}
+
+impl Lines {
+    pub fn new(subscription: Subscription, framing: LineFraming) -> Self {
+        Lines {
+            subscription,
+            framing,
+            partial: Vec::new(),
+            last: (0, SystemTime::UNIX_EPOCH),
+            flush: None,
+            ready: VecDeque::new(),
+            ended: false,
+        }
+    }
+
+    fn push(&mut self, chunk: Chunk) {
+        if !self.partial.is_empty() && chunk.seq != self.last.0 + 1 {
+            // its end has been dropped
+            debug!(
+                "chunks {}..{} missed, flushing the partial line",
+                self.last.0 + 1,
+                chunk.seq
+            );
+            self.flush_partial();
+        }
+        self.last = (chunk.seq, chunk.received_at);
+        // only what's new could complete a delimiter
+        let from = self
+            .partial
+            .len()
+            .saturating_sub(self.framing.delimiter.len() - 1);
+        self.partial.extend_from_slice(&chunk.data);
+        let len = self.framing.delimiter.len();
+        let mut start = 0;
+        let mut i = from;
+        while i < self.partial.len() {
+            if i - start == self.framing.max_length {
+                // too long, cut it at the max length.
+                // A delimiter starting before the cut is kept whole, with its line.
+                self.emit(start, i);
+                start = i;
+            } else if i + len > self.partial.len() {
+                // could be the start of a delimiter
+                break;
+            } else if self.partial[i..i + len] == self.framing.delimiter[..] {
+                let end = i + len;
+                self.emit(start, end);
+                start = end;
+                i = end;
+            } else {
+                i += 1;
+            }
+        }
+        self.partial.drain(..start);
+        if self.partial.is_empty() {
+            self.flush = None;
+        } else if start > 0 || self.flush.is_none() {
+            // the partial line has just started
+            self.flush = self
+                .framing
+                .flush_timeout
+                .map(|t| Box::pin(tokio::time::sleep(t)));
+        }
+    }
+
+    fn emit(&mut self, start: usize, end: usize) {
+        self.ready.push_back(Chunk {
+            seq: self.last.0,
+            received_at: self.last.1,
+            data: self.partial[start..end].to_vec(),
+        });
+    }
+
+    fn flush_partial(&mut self) {
+        self.flush = None;
+        if !self.partial.is_empty() {
+            let data = std::mem::take(&mut self.partial);
+            self.ready.push_back(Chunk {
+                seq: self.last.0,
+                received_at: self.last.1,
+                data,
+            });
+        }
+    }
+}
+
+impl Stream for Lines {
+    type Item = Chunk;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(line) = this.ready.pop_front() {
+                return Poll::Ready(Some(line));
+            }
+            if this.ended {
+                return Poll::Ready(None);
+            }
+            match Pin::new(&mut this.subscription).poll_next(cx) {
+                Poll::Ready(Some(chunk)) => this.push(chunk),
+                Poll::Ready(None) => {
+                    // the device is closed, whatever is left is the last line
+                    this.flush_partial();
+                    this.ended = true;
+                }
+                Poll::Pending => match this.flush.as_mut().map(|f| f.as_mut().poll(cx)) {
+                    Some(Poll::Ready(())) => this.flush_partial(),
+                    _ => return Poll::Pending,
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Subscribers;
+    use std::sync::Arc;
+
+    fn lines(delimiter: &[u8], max_length: usize) -> Lines {
+        let subscribers = Arc::new(Subscribers::new(8, api::OverflowPolicy::DropOldest));
+        let framing = LineFraming {
+            delimiter: delimiter.to_vec(),
+            max_length,
+            flush_timeout: None,
+        };
+        Lines::new(subscribers.subscribe(), framing)
+    }
+
+    fn chunk(seq: u64, data: &[u8]) -> Chunk {
+        Chunk {
+            seq,
+            received_at: SystemTime::UNIX_EPOCH,
+            data: data.to_vec(),
+        }
+    }
+
+    /// The lines emitted so far, with the seq they got
+    fn ready(lines: &mut Lines) -> Vec<(u64, Vec<u8>)> {
+        lines.ready.drain(..).map(|c| (c.seq, c.data)).collect()
+    }
+
+    #[test]
+    fn delimiter_split_across_chunks() {
+        let mut l = lines(b"\r\n", 64);
+        l.push(chunk(0, b"AB\r"));
+        assert!(ready(&mut l).is_empty());
+        l.push(chunk(1, b"\nCD"));
+        assert_eq!(ready(&mut l), vec![(1, b"AB\r\n".to_vec())]);
+        assert_eq!(l.partial, b"CD");
+    }
+
+    #[test]
+    fn line_longer_than_max_length() {
+        let mut l = lines(b"\n", 4);
+        l.push(chunk(0, b"ABCDEFGHIJ\n"));
+        assert_eq!(
+            ready(&mut l),
+            vec![
+                (0, b"ABCD".to_vec()),
+                (0, b"EFGH".to_vec()),
+                (0, b"IJ\n".to_vec()),
+            ]
+        );
+        assert!(l.partial.is_empty());
+    }
+
+    #[test]
+    fn delimiter_across_max_length() {
+        let mut l = lines(b"\r\n", 4);
+        l.push(chunk(0, b"ABC\r\nDEF\r\n"));
+        assert_eq!(
+            ready(&mut l),
+            vec![(0, b"ABC\r\n".to_vec()), (0, b"DEF\r\n".to_vec())]
+        );
+        assert!(l.partial.is_empty());
+    }
+
+    #[test]
+    fn seq_gap_flushes_partial_line() {
+        let mut l = lines(b"\n", 64);
+        l.push(chunk(0, b"AB"));
+        l.push(chunk(2, b"CD\n"));
+        assert_eq!(
+            ready(&mut l),
+            vec![(0, b"AB".to_vec()), (2, b"CD\n".to_vec())]
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use crate::serial::api::out as api;
+use crate::server::{sp_port_info_2_api, ManagedDevices, ManagedSerialDevice};
+use crate::watch;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+
+/// How often the ports are listed while someone is watching them
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub type Ports = HashMap<String, api::Serial>;
+
+pub type Watcher = watch::Watcher<Ports, api::DeviceEvent>;
+
+/// List the available ports and broadcast an event for each one that appears or disappears.
+pub fn spawn_watcher(managed: ManagedDevices) -> (Watcher, JoinHandle<()>) {
+    Watcher::spawn(
+        "serial ports",
+        POLL_INTERVAL,
+        move || {
+            let managed = managed.clone();
+            async move {
+                let mut ports = available_ports().await?;
+                annotate(&mut ports, &*managed.lock().await);
+                Ok(ports)
+            }
+        },
+        |prev, ports| {
+            let removed = prev
+                .iter()
+                .filter(|(n, _)| !ports.contains_key(*n))
+                .map(|(_, s)| event(api::DeviceEventType::Removed, s.clone()));
+            let added = ports
+                .iter()
+                .filter(|(n, _)| !prev.contains_key(*n))
+                .map(|(_, s)| event(api::DeviceEventType::Added, s.clone()));
+            removed.chain(added).collect()
+        },
+    )
+}
+
+/// Fill in the options of the ports that are managed
+pub fn annotate(ports: &mut Ports, managed: &HashMap<String, ManagedSerialDevice>) {
+    for (name, serial) in ports.iter_mut() {
+        serial.managed = managed.get(name).map(|d| d.options());
+    }
+}
+
+/// The available ports by name. Listing could block on udev so it's done off the runtime.
+pub async fn available_ports() -> Result<Ports, serialport::Error> {
+    let ports = tokio::task::spawn_blocking(serialport::available_ports)
+        .await
+        .map_err(|e| serialport::Error::new(serialport::ErrorKind::Unknown, e.to_string()))??;
+    Ok(ports
+        .into_iter()
+        .map(|p| (p.port_name.clone(), sp_port_info_2_api(p)))
+        .collect())
+}
+
+pub fn event(event_type: api::DeviceEventType, serial: api::Serial) -> api::DeviceEvent {
+    api::DeviceEvent {
+        timestamp: Some(SystemTime::now().into()),
+        event: event_type as i32,
+        serial: Some(serial),
+    }
+}
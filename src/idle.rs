@@ -0,0 +1,50 @@
+use crate::server::ManagedDevices;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// How often a device is checked for readers
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Close `device` once nobody has been reading from it for `timeout`.
+/// Stops on `cancel`, i.e. when the device is closed in the meantime.
+pub fn spawn_watcher(
+    device: String,
+    timeout: Duration,
+    managed: ManagedDevices,
+    cancel: CancellationToken,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut idle_since = Instant::now();
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = cancel.cancelled() => return,
+            }
+            let mut devices = managed.lock().await;
+            match devices.get(&device) {
+                Some(dev) if dev.idle() => {
+                    if idle_since.elapsed() < timeout {
+                        continue;
+                    }
+                }
+                Some(_) => {
+                    idle_since = Instant::now();
+                    continue;
+                }
+                None => return,
+            }
+            // don't hold the map while flushing
+            let dev = devices.remove(&device);
+            drop(devices);
+            if let Some(dev) = dev {
+                info!("{} has been idle for {:?}, closing it", device, timeout);
+                dev.close().await;
+            }
+            return;
+        }
+    })
+}
@@ -0,0 +1,273 @@
+//! Exclusive leases on the managed devices. While a device is leased, only the holder
+//! could write to it or change its settings and lines. Reading is never restricted.
+use crate::serial::api::out as api;
+use rand::Rng;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::info;
+
+/// The request metadata carrying the token of a lease
+pub const TOKEN_METADATA: &str = "lease-token";
+
+/// Used when the Acquire request doesn't say
+const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+struct Lease {
+    token: String,
+    holder: String,
+    /// `None` while a session holds it
+    expires: Option<(Instant, SystemTime)>,
+}
+
+/// The lease of a device, if any
+#[derive(Default)]
+pub struct LeaseSlot(parking_lot::Mutex<Option<Lease>>);
+
+impl LeaseSlot {
+    /// The live lease, after forgetting the expired one
+    fn live(lease: &mut Option<Lease>) -> Option<&mut Lease> {
+        if let Some(l) = lease {
+            if matches!(l.expires, Some((t, _)) if t <= Instant::now()) {
+                info!("lease of {} expired", l.holder);
+                *lease = None;
+            }
+        }
+        lease.as_mut()
+    }
+
+    /// Take the lease, or renew it if `token` is the one of the current lease
+    pub fn acquire(
+        &self,
+        holder: &str,
+        ttl: Option<Duration>,
+        token: Option<&str>,
+    ) -> Result<api::Lease, tonic::Status> {
+        let ttl = ttl.unwrap_or(DEFAULT_TTL);
+        let expires = Instant::now()
+            .checked_add(ttl)
+            .zip(SystemTime::now().checked_add(ttl))
+            .ok_or_else(|| tonic::Status::invalid_argument("ttl is too long"))?;
+        let expires = Some(expires);
+        let mut lease = self.0.lock();
+        match Self::live(&mut lease) {
+            Some(l) if Some(l.token.as_str()) == token => {
+                // a session holds it for as long as it lives
+                if l.expires.is_some() {
+                    l.expires = expires;
+                }
+                if !holder.is_empty() {
+                    l.holder = holder.to_string();
+                }
+            }
+            Some(l) => return Err(held_by(l)),
+            None => {
+                let token: [u8; 16] = rand::thread_rng().gen();
+                let token: String = token.iter().map(|b| format!("{:02x}", b)).collect();
+                info!("leased to {}", holder);
+                *lease = Some(Lease {
+                    token,
+                    holder: holder.to_string(),
+                    expires,
+                });
+            }
+        }
+        Ok(to_api(lease.as_ref().unwrap()))
+    }
+
+    pub fn release(&self, token: Option<&str>) -> Result<(), tonic::Status> {
+        let mut lease = self.0.lock();
+        match Self::live(&mut lease) {
+            Some(l) if Some(l.token.as_str()) == token => {
+                info!("lease of {} released", l.holder);
+                *lease = None;
+                Ok(())
+            }
+            Some(l) => Err(held_by(l)),
+            None => Err(tonic::Status::failed_precondition("not leased")),
+        }
+    }
+
+    /// Whether the holder of `token`, if any, could change the device
+    pub fn check(&self, token: Option<&str>) -> Result<(), tonic::Status> {
+        match Self::live(&mut self.0.lock()) {
+            Some(l) if Some(l.token.as_str()) != token => Err(held_by(l)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn holder(&self) -> Option<String> {
+        Self::live(&mut self.0.lock()).map(|l| l.holder.clone())
+    }
+
+    /// Keep the lease of `token` for as long as a session lives. Fails if it's not the current one.
+    pub fn bind(&self, token: &str) -> Result<(), tonic::Status> {
+        match Self::live(&mut self.0.lock()) {
+            Some(l) if l.token == token => {
+                l.expires = None;
+                Ok(())
+            }
+            Some(l) => Err(held_by(l)),
+            None => Err(tonic::Status::failed_precondition("not leased")),
+        }
+    }
+
+    /// Release the lease of a session that has ended, unless it was released in the meantime
+    pub fn unbind(&self, token: &str) {
+        let mut lease = self.0.lock();
+        if matches!(Self::live(&mut lease), Some(l) if l.token == token) {
+            info!(
+                "lease of {} released with its session",
+                lease.as_ref().unwrap().holder
+            );
+            *lease = None;
+        }
+    }
+}
+
+fn held_by(lease: &Lease) -> tonic::Status {
+    tonic::Status::failed_precondition(format!("leased by {}", lease.holder))
+}
+
+fn to_api(lease: &Lease) -> api::Lease {
+    api::Lease {
+        holder: lease.holder.clone(),
+        token: lease.token.clone(),
+        expires_at: lease.expires.map(|(_, t)| t.into()),
+    }
+}
+
+/// The lease token sent along with a request, if any
+pub fn token<T>(req: &tonic::Request<T>) -> Option<String> {
+    req.metadata()
+        .get(TOKEN_METADATA)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    const SHORT_TTL: Duration = Duration::from_millis(20);
+
+    fn acquire(slot: &LeaseSlot, holder: &str, ttl: Duration) -> String {
+        slot.acquire(holder, Some(ttl), None).unwrap().token
+    }
+
+    fn code<T>(r: Result<T, tonic::Status>) -> Option<Code> {
+        r.err().map(|e| e.code())
+    }
+
+    #[test]
+    fn only_the_holder_passes() {
+        let slot = LeaseSlot::default();
+        assert!(slot.check(None).is_ok());
+        let token = acquire(&slot, "ci", DEFAULT_TTL);
+        assert_eq!(slot.holder().as_deref(), Some("ci"));
+        assert!(slot.check(Some(&token)).is_ok());
+        assert_eq!(code(slot.check(None)), Some(Code::FailedPrecondition));
+        assert_eq!(
+            code(slot.check(Some("other"))),
+            Some(Code::FailedPrecondition)
+        );
+        assert_eq!(
+            code(slot.acquire("other", None, None)),
+            Some(Code::FailedPrecondition)
+        );
+    }
+
+    #[test]
+    fn renew() {
+        let slot = LeaseSlot::default();
+        let lease = slot.acquire("ci", Some(SHORT_TTL), None).unwrap();
+        let renewed = slot
+            .acquire("ci-2", Some(DEFAULT_TTL), Some(&lease.token))
+            .unwrap();
+        assert_eq!(renewed.token, lease.token);
+        assert_eq!(renewed.holder, "ci-2");
+        std::thread::sleep(SHORT_TTL * 2);
+        assert!(slot.check(Some(&lease.token)).is_ok());
+        assert_eq!(code(slot.check(None)), Some(Code::FailedPrecondition));
+    }
+
+    #[test]
+    fn expiry() {
+        let slot = LeaseSlot::default();
+        let token = acquire(&slot, "ci", SHORT_TTL);
+        std::thread::sleep(SHORT_TTL * 2);
+        assert_eq!(slot.holder(), None);
+        assert!(slot.check(None).is_ok());
+        // too late to renew it, that's a new lease
+        let renewed = slot.acquire("ci", Some(DEFAULT_TTL), Some(&token)).unwrap();
+        assert_ne!(renewed.token, token);
+        assert_eq!(
+            code(slot.check(Some(&token))),
+            Some(Code::FailedPrecondition)
+        );
+    }
+
+    #[test]
+    fn stale_token() {
+        let slot = LeaseSlot::default();
+        let token = acquire(&slot, "ci", DEFAULT_TTL);
+        assert_eq!(code(slot.release(None)), Some(Code::FailedPrecondition));
+        assert!(slot.release(Some(&token)).is_ok());
+        assert_eq!(
+            code(slot.release(Some(&token))),
+            Some(Code::FailedPrecondition)
+        );
+        let other = acquire(&slot, "other", DEFAULT_TTL);
+        assert_ne!(other, token);
+        assert_eq!(
+            code(slot.check(Some(&token))),
+            Some(Code::FailedPrecondition)
+        );
+        assert_eq!(
+            code(slot.release(Some(&token))),
+            Some(Code::FailedPrecondition)
+        );
+        assert_eq!(
+            code(slot.acquire("ci", None, Some(&token))),
+            Some(Code::FailedPrecondition)
+        );
+        assert_eq!(code(slot.bind(&token)), Some(Code::FailedPrecondition));
+        assert_eq!(slot.holder().as_deref(), Some("other"));
+    }
+
+    #[test]
+    fn bound_to_a_session() {
+        let slot = LeaseSlot::default();
+        let token = acquire(&slot, "session", SHORT_TTL);
+        slot.bind(&token).unwrap();
+        std::thread::sleep(SHORT_TTL * 2);
+        assert_eq!(slot.holder().as_deref(), Some("session"));
+        // renewing doesn't put a ttl back on it
+        let renewed = slot.acquire("", Some(SHORT_TTL), Some(&token)).unwrap();
+        assert_eq!(renewed.expires_at, None);
+        assert_eq!(renewed.holder, "session");
+        slot.unbind(&token);
+        assert_eq!(slot.holder(), None);
+    }
+
+    #[test]
+    fn unbind_keeps_a_newer_lease() {
+        let slot = LeaseSlot::default();
+        let token = acquire(&slot, "session", DEFAULT_TTL);
+        slot.bind(&token).unwrap();
+        slot.release(Some(&token)).unwrap();
+        let other = acquire(&slot, "other", DEFAULT_TTL);
+        slot.unbind(&token);
+        assert!(slot.check(Some(&other)).is_ok());
+        assert_eq!(slot.holder().as_deref(), Some("other"));
+    }
+
+    #[test]
+    fn ttl_too_long() {
+        let slot = LeaseSlot::default();
+        assert_eq!(
+            code(slot.acquire("ci", Some(Duration::MAX), None)),
+            Some(Code::InvalidArgument)
+        );
+        assert_eq!(slot.holder(), None);
+    }
+}
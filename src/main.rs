@@ -1,6 +1,77 @@
+// tonic::Status is large but it is what every RPC returns
+#![allow(clippy::result_large_err)]
+
+mod bridge;
+mod config;
+mod framing;
+mod hotplug;
+mod idle;
+mod lease;
+mod metrics;
+mod modem;
+mod selector;
 mod serial;
 mod server;
+mod session;
+mod status;
+mod watch;
+
+use clap::Parser;
+use serial::api::out::serial_service_server::SerialServiceServer;
+use tracing::info;
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut s) => {
+                s.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    info!("shutting down");
+}
 
-fn main() {
-    println!("Hello, world!");
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = config::Args::parse();
+    let config = config::Config::load(&args)?;
+    tracing_subscriber::fmt()
+        .with_max_level(config.log_level()?)
+        .init();
+    let server = server::SerialServer::new(config.clone());
+    if let Some(addr) = config.metrics_address {
+        metrics::spawn(addr, server.clone())
+            .map_err(|e| anyhow::anyhow!("failed to serve metrics on {}: {}", addr, e))?;
+        info!("serving metrics on http://{}/metrics", addr);
+    }
+    let addr = config.listen_addr();
+    info!("listening on {}", addr);
+    // the open streams end once the devices are closed, the server waits for them
+    let shutdown = async {
+        shutdown_signal().await;
+        server.close_all().await;
+    };
+    tonic::transport::Server::builder()
+        .add_service(SerialServiceServer::new(server.clone()))
+        .serve_with_shutdown(addr, shutdown)
+        .await?;
+    Ok(())
 }
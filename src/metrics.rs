@@ -0,0 +1,294 @@
+//! Prometheus metrics of the managed devices and of the process, in the text exposition format
+//! https://prometheus.io/docs/instrumenting/exposition_formats/
+use crate::serial::api::out as api;
+use crate::server::SerialServer;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::convert::Infallible;
+use std::fmt::{Display, Write};
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+use tracing::error;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+const STATES: [api::DeviceState; 6] = [
+    api::DeviceState::Opening,
+    api::DeviceState::Open,
+    api::DeviceState::Disconnected,
+    api::DeviceState::Reconnecting,
+    api::DeviceState::Closing,
+    api::DeviceState::Failed,
+];
+
+/// Serve `GET /metrics` on `addr`. Fails right away if it can't be bound.
+pub fn spawn(addr: SocketAddr, server: SerialServer) -> hyper::Result<JoinHandle<()>> {
+    let started = SystemTime::now();
+    let builder = hyper::Server::try_bind(&addr)?;
+    let make = make_service_fn(move |_| {
+        let server = server.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(req, server.clone(), started))) }
+    });
+    Ok(tokio::spawn(async move {
+        if let Err(e) = builder.serve(make).await {
+            error!("metrics server failed: {}", e);
+        }
+    }))
+}
+
+async fn handle(
+    req: Request<Body>,
+    server: SerialServer,
+    started: SystemTime,
+) -> Result<Response<Body>, Infallible> {
+    let response = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header(hyper::header::CONTENT_TYPE, CONTENT_TYPE)
+            .body(Body::from(render(&server, started).await)),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(response.expect("a valid response"))
+}
+
+/// Picks a per-device counter out of the stats
+type Counter = fn(&api::DeviceStats) -> u64;
+
+/// What's needed of a device, taken at once so the map isn't held while rendering
+struct Device {
+    status: api::DeviceStatus,
+    stats: api::DeviceStats,
+    subscribers: usize,
+    udp_peers: usize,
+}
+
+async fn render(server: &SerialServer, started: SystemTime) -> String {
+    let devices: Vec<Device> = {
+        let managed = server.managed_devices();
+        let managed = managed.lock().await;
+        managed
+            .values()
+            .map(|d| Device {
+                status: d.status(),
+                stats: d.stats(),
+                subscribers: d.subscribers(),
+                udp_peers: d.udp_peers(),
+            })
+            .collect()
+    };
+    let mut m = Metrics::default();
+
+    m.family(
+        "serial_managed_devices",
+        "gauge",
+        "Number of managed serial devices",
+    );
+    m.sample("serial_managed_devices", &[], devices.len());
+
+    m.family(
+        "serial_device_state",
+        "gauge",
+        "State of a managed device, 1 for the current one",
+    );
+    for d in &devices {
+        for state in STATES {
+            let name = state.as_str_name().to_lowercase();
+            let value = (d.status.state == state as i32) as u8;
+            m.sample(
+                "serial_device_state",
+                &[("device", &d.status.device), ("state", &name)],
+                value,
+            );
+        }
+    }
+    m.family(
+        "serial_device_opened_timestamp_seconds",
+        "gauge",
+        "When the device was opened, since the epoch",
+    );
+    for d in &devices {
+        let opened = d.status.opened_at.as_ref().map_or(0, |t| t.seconds);
+        m.sample(
+            "serial_device_opened_timestamp_seconds",
+            &[("device", &d.status.device)],
+            opened,
+        );
+    }
+
+    let counters: [(&str, &str, Counter); 8] = [
+        (
+            "serial_read_bytes_total",
+            "Bytes read from the device",
+            |s| s.bytes_read,
+        ),
+        (
+            "serial_read_chunks_total",
+            "Chunks read from the device",
+            |s| s.chunks_read,
+        ),
+        (
+            "serial_written_bytes_total",
+            "Bytes written to the device",
+            |s| s.bytes_written,
+        ),
+        (
+            "serial_written_chunks_total",
+            "Chunks written to the device",
+            |s| s.chunks_written,
+        ),
+        (
+            "serial_read_errors_total",
+            "Errors reading from the device",
+            |s| s.read_errors,
+        ),
+        (
+            "serial_write_errors_total",
+            "Errors writing to the device",
+            |s| s.write_errors,
+        ),
+        (
+            "serial_dropped_chunks_total",
+            "Chunks dropped for lagging readers",
+            |s| s.dropped_chunks,
+        ),
+        (
+            "serial_dropped_bytes_total",
+            "Bytes dropped for lagging readers",
+            |s| s.dropped_bytes,
+        ),
+    ];
+    for (name, help, value) in counters {
+        m.family(name, "counter", help);
+        for d in &devices {
+            m.sample(name, &[("device", &d.status.device)], value(&d.stats));
+        }
+    }
+
+    m.family(
+        "serial_subscribers",
+        "gauge",
+        "Read streams and bridge clients receiving the output of the device",
+    );
+    for d in &devices {
+        m.sample(
+            "serial_subscribers",
+            &[("device", &d.status.device)],
+            d.subscribers,
+        );
+    }
+    m.family(
+        "serial_clients",
+        "gauge",
+        "Open calls attached to the device, less the Close calls",
+    );
+    for d in &devices {
+        m.sample(
+            "serial_clients",
+            &[("device", &d.status.device)],
+            d.status.clients,
+        );
+    }
+    m.family("serial_udp_peers", "gauge", "Live peers of the UDP bridge");
+    for d in &devices {
+        m.sample(
+            "serial_udp_peers",
+            &[("device", &d.status.device)],
+            d.udp_peers,
+        );
+    }
+
+    m.family(
+        "process_start_time_seconds",
+        "gauge",
+        "Start time of the process since the epoch in seconds",
+    );
+    let start = started.duration_since(UNIX_EPOCH).unwrap_or_default();
+    m.sample("process_start_time_seconds", &[], start.as_secs());
+    #[cfg(target_os = "linux")]
+    process_metrics(&mut m);
+    m.0
+}
+
+/// The usual process_* metrics, read from /proc
+#[cfg(target_os = "linux")]
+fn process_metrics(m: &mut Metrics) {
+    // https://man7.org/linux/man-pages/man5/proc.5.html
+    if let Ok(stat) = std::fs::read_to_string("/proc/self/stat") {
+        // the fields after the command, which could contain anything but a ')'
+        let fields: Vec<&str> = match stat.rsplit_once(')') {
+            Some((_, rest)) => rest.split_whitespace().collect(),
+            None => Vec::new(),
+        };
+        let field = |n: usize| fields.get(n - 3).and_then(|f| f.parse::<u64>().ok());
+        // SAFETY: sysconf has no preconditions
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        if let (Some(utime), Some(stime)) = (field(14), field(15)) {
+            if ticks > 0 {
+                m.family(
+                    "process_cpu_seconds_total",
+                    "counter",
+                    "Total user and system CPU time spent in seconds",
+                );
+                let seconds = (utime + stime) as f64 / ticks as f64;
+                m.sample("process_cpu_seconds_total", &[], seconds);
+            }
+        }
+        if let Some(vsize) = field(23) {
+            m.family(
+                "process_virtual_memory_bytes",
+                "gauge",
+                "Virtual memory size in bytes",
+            );
+            m.sample("process_virtual_memory_bytes", &[], vsize);
+        }
+        if let Some(rss) = field(24) {
+            if page_size > 0 {
+                m.family(
+                    "process_resident_memory_bytes",
+                    "gauge",
+                    "Resident memory size in bytes",
+                );
+                m.sample("process_resident_memory_bytes", &[], rss * page_size as u64);
+            }
+        }
+    }
+    if let Ok(fds) = std::fs::read_dir("/proc/self/fd") {
+        m.family(
+            "process_open_fds",
+            "gauge",
+            "Number of open file descriptors",
+        );
+        m.sample("process_open_fds", &[], fds.count());
+    }
+}
+
+#[derive(Default)]
+struct Metrics(String);
+
+impl Metrics {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {} {}", name, help);
+        let _ = writeln!(self.0, "# TYPE {} {}", name, kind);
+    }
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        self.0.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+                .collect();
+            let _ = write!(self.0, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.0, " {}", value);
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
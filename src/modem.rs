@@ -0,0 +1,47 @@
+use crate::serial::api::out as api;
+use crate::server::PortControl;
+use crate::watch;
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+
+/// How often the modem lines are read while someone is watching them
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub type Watcher = watch::Watcher<api::ModemLines, api::ModemLinesEvent>;
+
+/// Poll the modem status lines of a port and broadcast an event whenever one of them changes.
+pub fn spawn_watcher(control: PortControl) -> (Watcher, JoinHandle<()>) {
+    Watcher::spawn(
+        "modem lines",
+        POLL_INTERVAL,
+        move || {
+            let control = control.clone();
+            async move { control.modem_lines().await }
+        },
+        |prev, lines| {
+            if prev == lines {
+                return Vec::new();
+            }
+            vec![event(changed(prev, lines), lines.clone())]
+        },
+    )
+}
+
+pub fn event(changed: api::ModemLines, lines: api::ModemLines) -> api::ModemLinesEvent {
+    api::ModemLinesEvent {
+        timestamp: Some(SystemTime::now().into()),
+        lines: Some(lines),
+        changed: Some(changed),
+    }
+}
+
+fn changed(a: &api::ModemLines, b: &api::ModemLines) -> api::ModemLines {
+    api::ModemLines {
+        cts: a.cts != b.cts,
+        dsr: a.dsr != b.dsr,
+        ri: a.ri != b.ri,
+        cd: a.cd != b.cd,
+        dtr: a.dtr != b.dtr,
+        rts: a.rts != b.rts,
+    }
+}
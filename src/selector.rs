@@ -0,0 +1,154 @@
+use crate::config::Config;
+use crate::serial::api::out as api;
+use serde::Deserialize;
+use serialport::{SerialPortInfo, SerialPortType};
+use std::path::{Path, PathBuf};
+
+/// Where the stable links to the USB serial devices are (Linux)
+const BY_ID_DIR: &str = "/dev/serial/by-id";
+
+/// Criteria a port must all match to be picked, e.g. an `[aliases.<name>]` of the config file
+/// or the [api::DeviceSelector] of an open request
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Selector {
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub serial_number: Option<String>,
+    /// a name in /dev/serial/by-id or a full path to a link
+    pub by_id: Option<String>,
+}
+
+impl Selector {
+    /// Merge the criteria of the request with the ones of its alias, if any
+    pub fn from_api(
+        selector: &api::DeviceSelector,
+        config: &Config,
+    ) -> Result<Self, tonic::Status> {
+        let mut s = match &selector.alias {
+            Some(alias) => config
+                .aliases
+                .get(alias)
+                .cloned()
+                .ok_or_else(|| tonic::Status::not_found(format!("unknown alias {}", alias)))?,
+            None => Selector::default(),
+        };
+        if let Some(vid) = selector.vid {
+            s.vid = Some(u16_or_invalid(vid, "vid")?);
+        }
+        if let Some(pid) = selector.pid {
+            s.pid = Some(u16_or_invalid(pid, "pid")?);
+        }
+        if selector.serial_number.is_some() {
+            s.serial_number = selector.serial_number.clone();
+        }
+        if selector.by_id.is_some() {
+            s.by_id = selector.by_id.clone();
+        }
+        if s.is_empty() {
+            return Err(tonic::Status::invalid_argument(
+                "the selector doesn't have any criteria",
+            ));
+        }
+        Ok(s)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vid.is_none()
+            && self.pid.is_none()
+            && self.serial_number.is_none()
+            && self.by_id.is_none()
+    }
+
+    /// Find the one available port matching every criteria.
+    /// Listing and following the links could block on udev so it's done off the runtime.
+    pub async fn resolve(&self) -> Result<String, tonic::Status> {
+        let selector = self.clone();
+        tokio::task::spawn_blocking(move || selector.resolve_blocking())
+            .await
+            .map_err(|e| tonic::Status::internal(e.to_string()))?
+    }
+
+    fn resolve_blocking(&self) -> Result<String, tonic::Status> {
+        let by_id = match &self.by_id {
+            Some(link) => {
+                let path = Path::new(BY_ID_DIR).join(link);
+                let target = std::fs::canonicalize(&path)
+                    .map_err(|e| tonic::Status::not_found(format!("{}: {}", path.display(), e)))?;
+                Some(target)
+            }
+            None => None,
+        };
+        let ports =
+            serialport::available_ports().map_err(|e| tonic::Status::internal(e.description))?;
+        let matching: Vec<String> = ports
+            .into_iter()
+            .filter(|p| self.matches(p, by_id.as_deref()))
+            .map(|p| p.port_name)
+            .collect();
+        match matching.len() {
+            0 => Err(tonic::Status::not_found(format!(
+                "no serial port matches {}",
+                self
+            ))),
+            1 => Ok(matching.into_iter().next().unwrap()),
+            _ => Err(tonic::Status::failed_precondition(format!(
+                "{} serial ports match {}: {}",
+                matching.len(),
+                self,
+                matching.join(", ")
+            ))),
+        }
+    }
+
+    // is_none_or needs Rust 1.82
+    #[allow(clippy::unnecessary_map_or)]
+    fn matches(&self, port: &SerialPortInfo, by_id: Option<&Path>) -> bool {
+        if let Some(target) = by_id {
+            // the port names are already canonical but a symlink won't hurt
+            let name = std::fs::canonicalize(&port.port_name)
+                .unwrap_or_else(|_| PathBuf::from(&port.port_name));
+            if name != target {
+                return false;
+            }
+        }
+        if self.vid.is_none() && self.pid.is_none() && self.serial_number.is_none() {
+            return true;
+        }
+        match &port.port_type {
+            SerialPortType::UsbPort(usb) => {
+                self.vid.map_or(true, |v| v == usb.vid)
+                    && self.pid.map_or(true, |p| p == usb.pid)
+                    && self
+                        .serial_number
+                        .as_ref()
+                        .map_or(true, |s| usb.serial_number.as_ref() == Some(s))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut criteria = Vec::new();
+        if let Some(vid) = self.vid {
+            criteria.push(format!("vid={:04x}", vid));
+        }
+        if let Some(pid) = self.pid {
+            criteria.push(format!("pid={:04x}", pid));
+        }
+        if let Some(serial) = &self.serial_number {
+            criteria.push(format!("serial_number={}", serial));
+        }
+        if let Some(link) = &self.by_id {
+            criteria.push(format!("by_id={}", link));
+        }
+        write!(f, "{}", criteria.join(" "))
+    }
+}
+
+fn u16_or_invalid(value: u32, name: &str) -> Result<u16, tonic::Status> {
+    u16::try_from(value)
+        .map_err(|_| tonic::Status::invalid_argument(format!("invalid {} {}", name, value)))
+}
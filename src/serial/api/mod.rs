@@ -1,2 +1,4 @@
 #[path = "serial.api.rs"]
+// generated, the variants are named after the proto
+#[allow(clippy::enum_variant_names)]
 pub mod out;
@@ -13,6 +13,34 @@ pub struct Serial {
     /// If the serial is managed by the server, we would see its baud rate and ports
     #[prost(message, optional, tag = "2")]
     pub managed: ::core::option::Option<ManagedOptions>,
+    ///
+    /// How the port is connected. Filled by List and by the WatchDevices events
+    #[prost(enumeration = "PortType", tag = "3")]
+    pub port_type: i32,
+    /// only for USB ports
+    #[prost(message, optional, tag = "4")]
+    pub usb: ::core::option::Option<UsbInfo>,
+    /// only for managed ports
+    #[prost(message, optional, tag = "5")]
+    pub status: ::core::option::Option<DeviceStatus>,
+}
+/// * <https://docs.rs/serialport/latest/serialport/struct.UsbPortInfo.html>
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UsbInfo {
+    /// vendor ID
+    #[prost(uint32, tag = "1")]
+    pub vid: u32,
+    /// product ID
+    #[prost(uint32, tag = "2")]
+    pub pid: u32,
+    /// arbitrary strings reported by the device, if any
+    #[prost(string, optional, tag = "3")]
+    pub serial_number: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(string, optional, tag = "4")]
+    pub manufacturer: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(string, optional, tag = "5")]
+    pub product: ::core::option::Option<::prost::alloc::string::String>,
 }
 /// <https://docs.rs/serialport/latest/serialport/struct.SerialPortBuilder.html>
 /// <https://github.com/protocolbuffers/protobuf/blob/main/src/google/protobuf/duration.proto>
@@ -33,6 +61,56 @@ pub struct OpenOptions {
     /// Set the amount of time to wait to receive data before timing out
     #[prost(message, optional, tag = "6")]
     pub timeout: ::core::option::Option<::prost_types::Duration>,
+    ///
+    /// Address to bind the UDP socket to, e.g. 127.0.0.1 to only accept local peers.
+    /// Leave empty to use the server default.
+    #[prost(string, tag = "7")]
+    pub udp_bind_address: ::prost::alloc::string::String,
+    /// UDP port to bind to. 0 picks a free one in the port range of the server
+    #[prost(uint32, tag = "8")]
+    pub udp_port: u32,
+    ///
+    /// Also listen on a raw TCP socket (ser2net-style), e.g. for `nc` or pyserial's socket://
+    /// Every connection gets the serial output and whatever it sends is written to the serial port.
+    #[prost(bool, tag = "9")]
+    pub tcp: bool,
+    /// Same as udpBindAddress, for the TCP listener
+    #[prost(string, tag = "10")]
+    pub tcp_bind_address: ::prost::alloc::string::String,
+    /// Same as udpPort, for the TCP listener
+    #[prost(uint32, tag = "11")]
+    pub tcp_port: u32,
+    ///
+    /// Also listen for RFC 2217 (Telnet COM port control) clients, e.g. pyserial's rfc2217://
+    /// which could change the baud rate, parity, data bits, stop bits and flow control remotely.
+    #[prost(bool, tag = "12")]
+    pub rfc2217: bool,
+    /// Same as udpBindAddress, for the RFC 2217 listener
+    #[prost(string, tag = "13")]
+    pub rfc2217_bind_address: ::prost::alloc::string::String,
+    /// Same as udpPort, for the RFC 2217 listener
+    #[prost(uint32, tag = "14")]
+    pub rfc2217_port: u32,
+    ///
+    /// Reopen the port with the same options when it comes back after being unplugged.
+    /// It's matched by its USB serial number if it has one, by path otherwise.
+    /// Either way it stays managed under the name it was opened with.
+    #[prost(bool, tag = "15")]
+    pub reconnect: bool,
+    /// What to do when a Read stream or a bridge lags behind the port
+    #[prost(enumeration = "OverflowPolicy", tag = "16")]
+    pub overflow_policy: i32,
+    /// How many chunks each of them could lag behind, 8 if 0
+    #[prost(uint32, tag = "17")]
+    pub reader_capacity: u32,
+    /// How many bytes are read from the port at once, i.e. the largest chunk, 512 if 0
+    #[prost(uint32, tag = "18")]
+    pub read_size: u32,
+    ///
+    /// Close the device once nobody has been reading from it for that long, i.e. there's been
+    /// no Read stream, Session, TCP or RFC 2217 client, nor UDP peer. Never if unset.
+    #[prost(message, optional, tag = "19")]
+    pub idle_timeout: ::core::option::Option<::prost_types::Duration>,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -44,6 +122,19 @@ pub struct ManagedOptions {
     /// outside <- serial device (read/slurp)
     #[prost(int32, tag = "2")]
     pub udp_port: i32,
+    /// The address the UDP socket is bound to
+    #[prost(string, tag = "3")]
+    pub udp_bind_address: ::prost::alloc::string::String,
+    /// -1 if not listening on TCP
+    #[prost(int32, tag = "4")]
+    pub tcp_port: i32,
+    #[prost(string, tag = "5")]
+    pub tcp_bind_address: ::prost::alloc::string::String,
+    /// -1 if not listening for RFC 2217
+    #[prost(int32, tag = "6")]
+    pub rfc2217_port: i32,
+    #[prost(string, tag = "7")]
+    pub rfc2217_bind_address: ::prost::alloc::string::String,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -54,22 +145,78 @@ pub struct ListResponse {
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct OpenRequest {
+    /// leave empty when opening by selector
     #[prost(string, tag = "1")]
     pub device: ::prost::alloc::string::String,
     #[prost(message, optional, tag = "2")]
     pub options: ::core::option::Option<OpenOptions>,
+    ///
+    /// Open the one available port matching the selector instead of `device`.
+    /// The path it resolves to is returned and is what the other RPCs expect.
+    #[prost(message, optional, tag = "3")]
+    pub selector: ::core::option::Option<DeviceSelector>,
+}
+///
+/// The port must match every criteria that is set.
+/// It's NOT_FOUND if no port matches and FAILED_PRECONDITION if several do.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeviceSelector {
+    /// USB vendor and product IDs
+    #[prost(uint32, optional, tag = "1")]
+    pub vid: ::core::option::Option<u32>,
+    #[prost(uint32, optional, tag = "2")]
+    pub pid: ::core::option::Option<u32>,
+    /// USB serial number
+    #[prost(string, optional, tag = "3")]
+    pub serial_number: ::core::option::Option<::prost::alloc::string::String>,
+    /// a name in /dev/serial/by-id, e.g. usb-FTDI_FT232R_USB_UART_A50285BI-if00-port0
+    #[prost(string, optional, tag = "4")]
+    pub by_id: ::core::option::Option<::prost::alloc::string::String>,
+    /// an alias from the config file, the other criteria are added to its own
+    #[prost(string, optional, tag = "5")]
+    pub alias: ::core::option::Option<::prost::alloc::string::String>,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Bytes {
     #[prost(bytes = "vec", tag = "1")]
     pub value: ::prost::alloc::vec::Vec<u8>,
+    ///
+    /// Counted from 0 once the device is opened, across reconnections.
+    /// A gap means that the stream has lagged behind and missed some chunks.
+    #[prost(uint64, tag = "2")]
+    pub seq: u64,
+    /// when it was read from the device, by the clock of the server
+    #[prost(message, optional, tag = "3")]
+    pub timestamp: ::core::option::Option<::prost_types::Timestamp>,
+    #[prost(string, tag = "4")]
+    pub device: ::prost::alloc::string::String,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ReadRequest {
     #[prost(string, tag = "1")]
     pub device: ::prost::alloc::string::String,
+    /// stream whole lines rather than chunks as they're read, if set
+    #[prost(message, optional, tag = "2")]
+    pub lines: ::core::option::Option<LineFraming>,
+}
+///
+/// Every Bytes is one line, delimiter included, with the seq and timestamp of the chunk that completes it.
+/// A partial line is sent as is when the device is closed or when chunks have been missed in the middle of it.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LineFraming {
+    /// e.g. "\r\n" or "\0", "\n" if empty
+    #[prost(bytes = "vec", tag = "1")]
+    pub delimiter: ::prost::alloc::vec::Vec<u8>,
+    /// a longer line is split, 4096 if 0. A delimiter is never split, it's kept whole with its line.
+    #[prost(uint32, tag = "2")]
+    pub max_length: u32,
+    /// send a partial line once it has waited that long for its delimiter. Never if unset.
+    #[prost(message, optional, tag = "3")]
+    pub flush_timeout: ::core::option::Option<::prost_types::Duration>,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -84,6 +231,316 @@ pub struct WriteRequest {
 pub struct CloseRequest {
     #[prost(string, tag = "1")]
     pub device: ::prost::alloc::string::String,
+    /// close it even if other clients are still attached to it
+    #[prost(bool, tag = "2")]
+    pub force: bool,
+}
+/// <https://docs.rs/serialport/latest/serialport/trait.SerialPort.html>
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ModemLines {
+    /// inputs, read from the device
+    #[prost(bool, tag = "1")]
+    pub cts: bool,
+    #[prost(bool, tag = "2")]
+    pub dsr: bool,
+    #[prost(bool, tag = "3")]
+    pub ri: bool,
+    #[prost(bool, tag = "4")]
+    pub cd: bool,
+    /// outputs, as last set since they can't be read back
+    #[prost(bool, tag = "5")]
+    pub dtr: bool,
+    #[prost(bool, tag = "6")]
+    pub rts: bool,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ModemLinesEvent {
+    #[prost(message, optional, tag = "1")]
+    pub timestamp: ::core::option::Option<::prost_types::Timestamp>,
+    /// the state after the change
+    #[prost(message, optional, tag = "2")]
+    pub lines: ::core::option::Option<ModemLines>,
+    /// true for the lines that have changed since the previous event
+    #[prost(message, optional, tag = "3")]
+    pub changed: ::core::option::Option<ModemLines>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ModemLinesRequest {
+    #[prost(string, tag = "1")]
+    pub device: ::prost::alloc::string::String,
+}
+///
+/// DTR is set before RTS. Call it twice if you need the other order,
+/// e.g. to reset an ESP32 into its bootloader.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SetModemLinesRequest {
+    #[prost(string, tag = "1")]
+    pub device: ::prost::alloc::string::String,
+    /// leave unset to keep the line as it is
+    #[prost(bool, optional, tag = "2")]
+    pub dtr: ::core::option::Option<bool>,
+    #[prost(bool, optional, tag = "3")]
+    pub rts: ::core::option::Option<bool>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BreakRequest {
+    #[prost(string, tag = "1")]
+    pub device: ::prost::alloc::string::String,
+    /// assert the break for this long then clear it, e.g. 250ms for a Linux SysRq
+    #[prost(message, optional, tag = "2")]
+    pub duration: ::core::option::Option<::prost_types::Duration>,
+    /// without a duration, set (true) or clear (false) the break until told otherwise
+    #[prost(bool, tag = "3")]
+    pub on: bool,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeviceEvent {
+    #[prost(message, optional, tag = "1")]
+    pub timestamp: ::core::option::Option<::prost_types::Timestamp>,
+    #[prost(enumeration = "DeviceEventType", tag = "2")]
+    pub event: i32,
+    ///
+    /// As it would be listed. For a removed port, `managed` is set if the server still had it open.
+    #[prost(message, optional, tag = "3")]
+    pub serial: ::core::option::Option<Serial>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeviceStatus {
+    #[prost(string, tag = "1")]
+    pub device: ::prost::alloc::string::String,
+    #[prost(enumeration = "DeviceState", tag = "2")]
+    pub state: i32,
+    /// empty if nothing went wrong yet
+    #[prost(string, tag = "3")]
+    pub last_error: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "4")]
+    pub last_error_time: ::core::option::Option<::prost_types::Timestamp>,
+    #[prost(message, optional, tag = "5")]
+    pub opened_at: ::core::option::Option<::prost_types::Timestamp>,
+    #[prost(uint64, tag = "6")]
+    pub bytes_read: u64,
+    #[prost(uint64, tag = "7")]
+    pub bytes_written: u64,
+    /// who holds the lease, empty if the device isn't leased
+    #[prost(string, tag = "8")]
+    pub lease_holder: ::prost::alloc::string::String,
+    /// how many Open calls it's attached to, less the Close calls
+    #[prost(uint32, tag = "9")]
+    pub clients: u32,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StatusRequest {
+    #[prost(string, tag = "1")]
+    pub device: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StatsRequest {
+    #[prost(string, tag = "1")]
+    pub device: ::prost::alloc::string::String,
+}
+/// Counted since the device was opened
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeviceStats {
+    #[prost(string, tag = "1")]
+    pub device: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "2")]
+    pub bytes_read: u64,
+    #[prost(uint64, tag = "3")]
+    pub chunks_read: u64,
+    #[prost(uint64, tag = "4")]
+    pub bytes_written: u64,
+    #[prost(uint64, tag = "5")]
+    pub chunks_written: u64,
+    #[prost(uint64, tag = "6")]
+    pub read_errors: u64,
+    #[prost(uint64, tag = "7")]
+    pub write_errors: u64,
+    /// chunks a Read stream or a bridge didn't get because it was lagging behind
+    #[prost(uint64, tag = "8")]
+    pub dropped_chunks: u64,
+    #[prost(uint64, tag = "11")]
+    pub dropped_bytes: u64,
+    /// bytes per second, over the last complete second
+    #[prost(uint64, tag = "9")]
+    pub rx_rate: u64,
+    #[prost(uint64, tag = "10")]
+    pub tx_rate: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Attach {
+    #[prost(string, tag = "1")]
+    pub device: ::prost::alloc::string::String,
+    /// also stream the modem line changes, as WatchModemLines would
+    #[prost(bool, tag = "2")]
+    pub modem_lines: bool,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ModemLinesCommand {
+    /// leave unset to keep the line as it is
+    #[prost(bool, optional, tag = "1")]
+    pub dtr: ::core::option::Option<bool>,
+    #[prost(bool, optional, tag = "2")]
+    pub rts: ::core::option::Option<bool>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BreakCommand {
+    /// same as BreakRequest
+    #[prost(message, optional, tag = "1")]
+    pub duration: ::core::option::Option<::prost_types::Duration>,
+    #[prost(bool, tag = "2")]
+    pub on: bool,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ClientMessage {
+    /// chosen by the client, echoed in the Ack of this message
+    #[prost(uint64, tag = "1")]
+    pub id: u64,
+    #[prost(oneof = "client_message::Command", tags = "2, 3, 4, 5, 6")]
+    pub command: ::core::option::Option<client_message::Command>,
+}
+/// Nested message and enum types in `ClientMessage`.
+pub mod client_message {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Command {
+        /// must be the first message, and only the first
+        #[prost(message, tag = "2")]
+        Attach(super::Attach),
+        #[prost(bytes, tag = "3")]
+        Data(::prost::alloc::vec::Vec<u8>),
+        #[prost(message, tag = "4")]
+        ModemLines(super::ModemLinesCommand),
+        #[prost(message, tag = "5")]
+        Break(super::BreakCommand),
+        /// the serial settings to apply, as ConfigureRequest
+        #[prost(message, tag = "6")]
+        Configure(super::OpenOptions),
+    }
+}
+///
+/// The outcome of a ClientMessage, once it's been carried out.
+/// For data, that's once it's drained to the device.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Ack {
+    #[prost(uint64, tag = "1")]
+    pub id: u64,
+    /// a gRPC status code, 0 (OK) if it succeeded
+    #[prost(int32, tag = "2")]
+    pub code: i32,
+    #[prost(string, tag = "3")]
+    pub message: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ServerMessage {
+    #[prost(oneof = "server_message::Message", tags = "1, 2, 3, 4")]
+    pub message: ::core::option::Option<server_message::Message>,
+}
+/// Nested message and enum types in `ServerMessage`.
+pub mod server_message {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Message {
+        #[prost(message, tag = "1")]
+        Data(super::Bytes),
+        #[prost(message, tag = "2")]
+        Ack(super::Ack),
+        /// only if asked for when attaching
+        #[prost(message, tag = "3")]
+        ModemLines(super::ModemLinesEvent),
+        /// sent after attaching, then whenever the state changes
+        #[prost(message, tag = "4")]
+        Status(super::DeviceStatus),
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AcquireRequest {
+    #[prost(string, tag = "1")]
+    pub device: ::prost::alloc::string::String,
+    /// who's asking, shown to the others, e.g. a CI job name
+    #[prost(string, tag = "2")]
+    pub holder: ::prost::alloc::string::String,
+    /// 60s if unset. Acquire again with the token to renew the lease.
+    #[prost(message, optional, tag = "3")]
+    pub ttl: ::core::option::Option<::prost_types::Duration>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ReleaseRequest {
+    #[prost(string, tag = "1")]
+    pub device: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lease {
+    #[prost(string, tag = "1")]
+    pub holder: ::prost::alloc::string::String,
+    /// to be sent as `lease-token` metadata along with the requests that need the lease
+    #[prost(string, tag = "2")]
+    pub token: ::prost::alloc::string::String,
+    /// unset while a Session sent with the token holds the lease
+    #[prost(message, optional, tag = "3")]
+    pub expires_at: ::core::option::Option<::prost_types::Timestamp>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ConfigureRequest {
+    #[prost(string, tag = "1")]
+    pub device: ::prost::alloc::string::String,
+    ///
+    /// The serial settings (baud, data bits, flow control, parity, stop bits and timeout) to apply.
+    /// The UDP/TCP/RFC 2217 settings can't be changed without reopening and are ignored.
+    #[prost(message, optional, tag = "2")]
+    pub options: ::core::option::Option<OpenOptions>,
+}
+/// * <https://docs.rs/serialport/latest/serialport/enum.SerialPortType.html>
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum PortType {
+    UnknownPort = 0,
+    Usb = 1,
+    Pci = 2,
+    Bluetooth = 3,
+}
+impl PortType {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            PortType::UnknownPort => "UnknownPort",
+            PortType::Usb => "Usb",
+            PortType::Pci => "Pci",
+            PortType::Bluetooth => "Bluetooth",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "UnknownPort" => Some(Self::UnknownPort),
+            "Usb" => Some(Self::Usb),
+            "Pci" => Some(Self::Pci),
+            "Bluetooth" => Some(Self::Bluetooth),
+            _ => None,
+        }
+    }
 }
 /// * <https://docs.rs/serialport/latest/serialport/enum.DataBits.html>
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
@@ -205,6 +662,108 @@ impl StopBits {
         }
     }
 }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum OverflowPolicy {
+    /// keep the most recent data
+    DropOldest = 0,
+    /// keep the data that's already queued
+    DropNewest = 1,
+    ///
+    /// Stop reading the port until every reader has room.
+    /// Nothing is lost on our side but a stuck reader stalls the others,
+    /// and the OS buffer of the port could overflow in the meantime.
+    Block = 2,
+}
+impl OverflowPolicy {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            OverflowPolicy::DropOldest => "DropOldest",
+            OverflowPolicy::DropNewest => "DropNewest",
+            OverflowPolicy::Block => "Block",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "DropOldest" => Some(Self::DropOldest),
+            "DropNewest" => Some(Self::DropNewest),
+            "Block" => Some(Self::Block),
+            _ => None,
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum DeviceEventType {
+    Added = 0,
+    Removed = 1,
+}
+impl DeviceEventType {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            DeviceEventType::Added => "Added",
+            DeviceEventType::Removed => "Removed",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "Added" => Some(Self::Added),
+            "Removed" => Some(Self::Removed),
+            _ => None,
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum DeviceState {
+    Opening = 0,
+    Open = 1,
+    /// unplugged, not to be reconnected
+    Disconnected = 2,
+    /// unplugged, waiting for it to come back
+    Reconnecting = 3,
+    Closing = 4,
+    /// reading failed for another reason than being unplugged
+    Failed = 5,
+}
+impl DeviceState {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            DeviceState::Opening => "Opening",
+            DeviceState::Open => "Open",
+            DeviceState::Disconnected => "Disconnected",
+            DeviceState::Reconnecting => "Reconnecting",
+            DeviceState::Closing => "Closing",
+            DeviceState::Failed => "Failed",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "Opening" => Some(Self::Opening),
+            "Open" => Some(Self::Open),
+            "Disconnected" => Some(Self::Disconnected),
+            "Reconnecting" => Some(Self::Reconnecting),
+            "Closing" => Some(Self::Closing),
+            "Failed" => Some(Self::Failed),
+            _ => None,
+        }
+    }
+}
 /// Generated server implementations.
 pub mod serial_service_server {
     #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
@@ -224,6 +783,8 @@ pub mod serial_service_server {
         /// @brief Open a serial connection to a device
         /// @param OpenSerialRequest The request message containing the device name and baud rate
         /// @return Serial The opened serial
+        /// @note If it's already managed, the call attaches to it as long as the options match,
+        ///       otherwise it fails with ALREADY_EXISTS. Unset ports and bind addresses match any.
         async fn open(
             &self,
             request: tonic::Request<super::OpenRequest>,
@@ -231,6 +792,7 @@ pub mod serial_service_server {
         ///
         /// @brief Close the serial
         /// @param device The device to close
+        /// @note It's only closed once every client that opened it has closed it, unless forced
         async fn close(
             &self,
             request: tonic::Request<super::CloseRequest>,
@@ -257,6 +819,113 @@ pub mod serial_service_server {
             &self,
             request: tonic::Request<super::WriteRequest>,
         ) -> std::result::Result<tonic::Response<()>, tonic::Status>;
+        ///
+        /// @brief Change the settings of a managed serial device without closing it
+        /// @param ConfigureRequest The request message containing the device name and the new options
+        /// @return Serial The serial with the updated options
+        async fn configure(
+            &self,
+            request: tonic::Request<super::ConfigureRequest>,
+        ) -> std::result::Result<tonic::Response<super::Serial>, tonic::Status>;
+        ///
+        /// @brief Set the DTR and/or RTS lines of a managed serial device
+        /// @return ModemLines The state of the modem lines after they're set
+        async fn set_modem_lines(
+            &self,
+            request: tonic::Request<super::SetModemLinesRequest>,
+        ) -> std::result::Result<tonic::Response<super::ModemLines>, tonic::Status>;
+        ///
+        /// @brief Read the CTS/DSR/RI/CD lines of a managed serial device
+        async fn get_modem_lines(
+            &self,
+            request: tonic::Request<super::ModemLinesRequest>,
+        ) -> std::result::Result<tonic::Response<super::ModemLines>, tonic::Status>;
+        /// Server streaming response type for the WatchModemLines method.
+        type WatchModemLinesStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::ModemLinesEvent, tonic::Status>,
+            >
+            + Send
+            + 'static;
+        ///
+        /// @brief Watch the modem lines of a managed serial device
+        /// @return stream ModemLinesEvent The current state first, then an event whenever a line changes
+        /// @note The lines are polled, so very short pulses (e.g. RI) could be missed
+        async fn watch_modem_lines(
+            &self,
+            request: tonic::Request<super::ModemLinesRequest>,
+        ) -> std::result::Result<
+            tonic::Response<Self::WatchModemLinesStream>,
+            tonic::Status,
+        >;
+        ///
+        /// @brief Send a BREAK condition on a managed serial device
+        /// @param BreakRequest The request message containing the device name and either a duration or on/off
+        /// @note A timed break returns once it's cleared
+        async fn r#break(
+            &self,
+            request: tonic::Request<super::BreakRequest>,
+        ) -> std::result::Result<tonic::Response<()>, tonic::Status>;
+        /// Server streaming response type for the WatchDevices method.
+        type WatchDevicesStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::DeviceEvent, tonic::Status>,
+            >
+            + Send
+            + 'static;
+        ///
+        /// @brief Watch serial devices being plugged and unplugged
+        /// @return stream DeviceEvent The ports already present as Added first, then an event whenever one appears or disappears
+        /// @note The ports are polled, so a port replugged quickly could go unnoticed
+        async fn watch_devices(
+            &self,
+            request: tonic::Request<()>,
+        ) -> std::result::Result<
+            tonic::Response<Self::WatchDevicesStream>,
+            tonic::Status,
+        >;
+        ///
+        /// @brief Get the state of a managed serial device, e.g. to see why it's unhealthy
+        async fn status(
+            &self,
+            request: tonic::Request<super::StatusRequest>,
+        ) -> std::result::Result<tonic::Response<super::DeviceStatus>, tonic::Status>;
+        ///
+        /// @brief Get the traffic counters of a managed serial device
+        async fn stats(
+            &self,
+            request: tonic::Request<super::StatsRequest>,
+        ) -> std::result::Result<tonic::Response<super::DeviceStats>, tonic::Status>;
+        /// Server streaming response type for the Session method.
+        type SessionStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::ServerMessage, tonic::Status>,
+            >
+            + Send
+            + 'static;
+        ///
+        /// @brief Write to, control and read a managed serial device over a single stream
+        /// @param stream ClientMessage An Attach first, then data and commands, run in order
+        /// @return stream ServerMessage The data read from the device, the Acks and the status changes
+        /// @note The stream ends when the device is closed
+        async fn session(
+            &self,
+            request: tonic::Request<tonic::Streaming<super::ClientMessage>>,
+        ) -> std::result::Result<tonic::Response<Self::SessionStream>, tonic::Status>;
+        ///
+        /// @brief Lease a managed serial device exclusively
+        /// @return Lease Its token is to be sent as `lease-token` metadata
+        /// @note While it's leased, Write, Configure, SetModemLines and Break fail with
+        ///       FAILED_PRECONDITION without the token, and the bridges can't change anything.
+        ///       Reading is never restricted.
+        ///       A Session sent with the token holds the lease until it ends, whatever the ttl.
+        async fn acquire(
+            &self,
+            request: tonic::Request<super::AcquireRequest>,
+        ) -> std::result::Result<tonic::Response<super::Lease>, tonic::Status>;
+        ///
+        /// @brief Give up the lease of a managed serial device, its token is expected as metadata
+        async fn release(
+            &self,
+            request: tonic::Request<super::ReleaseRequest>,
+        ) -> std::result::Result<tonic::Response<()>, tonic::Status>;
     }
     #[derive(Debug)]
     pub struct SerialServiceServer<T: SerialService> {
@@ -560,6 +1229,511 @@ pub mod serial_service_server {
                     };
                     Box::pin(fut)
                 }
+                "/serial.api.SerialService/Configure" => {
+                    #[allow(non_camel_case_types)]
+                    struct ConfigureSvc<T: SerialService>(pub Arc<T>);
+                    impl<
+                        T: SerialService,
+                    > tonic::server::UnaryService<super::ConfigureRequest>
+                    for ConfigureSvc<T> {
+                        type Response = super::Serial;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ConfigureRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as SerialService>::configure(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ConfigureSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/serial.api.SerialService/SetModemLines" => {
+                    #[allow(non_camel_case_types)]
+                    struct SetModemLinesSvc<T: SerialService>(pub Arc<T>);
+                    impl<
+                        T: SerialService,
+                    > tonic::server::UnaryService<super::SetModemLinesRequest>
+                    for SetModemLinesSvc<T> {
+                        type Response = super::ModemLines;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::SetModemLinesRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as SerialService>::set_modem_lines(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = SetModemLinesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/serial.api.SerialService/GetModemLines" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetModemLinesSvc<T: SerialService>(pub Arc<T>);
+                    impl<
+                        T: SerialService,
+                    > tonic::server::UnaryService<super::ModemLinesRequest>
+                    for GetModemLinesSvc<T> {
+                        type Response = super::ModemLines;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ModemLinesRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as SerialService>::get_modem_lines(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = GetModemLinesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/serial.api.SerialService/WatchModemLines" => {
+                    #[allow(non_camel_case_types)]
+                    struct WatchModemLinesSvc<T: SerialService>(pub Arc<T>);
+                    impl<
+                        T: SerialService,
+                    > tonic::server::ServerStreamingService<super::ModemLinesRequest>
+                    for WatchModemLinesSvc<T> {
+                        type Response = super::ModemLinesEvent;
+                        type ResponseStream = T::WatchModemLinesStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ModemLinesRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as SerialService>::watch_modem_lines(&inner, request)
+                                    .await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = WatchModemLinesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/serial.api.SerialService/Break" => {
+                    #[allow(non_camel_case_types)]
+                    struct BreakSvc<T: SerialService>(pub Arc<T>);
+                    impl<
+                        T: SerialService,
+                    > tonic::server::UnaryService<super::BreakRequest> for BreakSvc<T> {
+                        type Response = ();
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::BreakRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as SerialService>::r#break(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = BreakSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/serial.api.SerialService/WatchDevices" => {
+                    #[allow(non_camel_case_types)]
+                    struct WatchDevicesSvc<T: SerialService>(pub Arc<T>);
+                    impl<T: SerialService> tonic::server::ServerStreamingService<()>
+                    for WatchDevicesSvc<T> {
+                        type Response = super::DeviceEvent;
+                        type ResponseStream = T::WatchDevicesStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(&mut self, request: tonic::Request<()>) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as SerialService>::watch_devices(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = WatchDevicesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/serial.api.SerialService/Status" => {
+                    #[allow(non_camel_case_types)]
+                    struct StatusSvc<T: SerialService>(pub Arc<T>);
+                    impl<
+                        T: SerialService,
+                    > tonic::server::UnaryService<super::StatusRequest>
+                    for StatusSvc<T> {
+                        type Response = super::DeviceStatus;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::StatusRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as SerialService>::status(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = StatusSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/serial.api.SerialService/Stats" => {
+                    #[allow(non_camel_case_types)]
+                    struct StatsSvc<T: SerialService>(pub Arc<T>);
+                    impl<
+                        T: SerialService,
+                    > tonic::server::UnaryService<super::StatsRequest> for StatsSvc<T> {
+                        type Response = super::DeviceStats;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::StatsRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as SerialService>::stats(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = StatsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/serial.api.SerialService/Session" => {
+                    #[allow(non_camel_case_types)]
+                    struct SessionSvc<T: SerialService>(pub Arc<T>);
+                    impl<
+                        T: SerialService,
+                    > tonic::server::StreamingService<super::ClientMessage>
+                    for SessionSvc<T> {
+                        type Response = super::ServerMessage;
+                        type ResponseStream = T::SessionStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<
+                                tonic::Streaming<super::ClientMessage>,
+                            >,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as SerialService>::session(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = SessionSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/serial.api.SerialService/Acquire" => {
+                    #[allow(non_camel_case_types)]
+                    struct AcquireSvc<T: SerialService>(pub Arc<T>);
+                    impl<
+                        T: SerialService,
+                    > tonic::server::UnaryService<super::AcquireRequest>
+                    for AcquireSvc<T> {
+                        type Response = super::Lease;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::AcquireRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as SerialService>::acquire(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = AcquireSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/serial.api.SerialService/Release" => {
+                    #[allow(non_camel_case_types)]
+                    struct ReleaseSvc<T: SerialService>(pub Arc<T>);
+                    impl<
+                        T: SerialService,
+                    > tonic::server::UnaryService<super::ReleaseRequest>
+                    for ReleaseSvc<T> {
+                        type Response = ();
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ReleaseRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as SerialService>::release(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ReleaseSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         Ok(
@@ -1,18 +1,34 @@
+use crate::bridge;
+use crate::config::Config;
+use crate::framing::{self, LineFraming};
+use crate::hotplug;
+use crate::idle;
+use crate::lease::{self, LeaseSlot};
+use crate::modem;
+use crate::selector::Selector;
 use crate::serial::api::out as api;
 use crate::serial::api::out::serial_service_server as service;
-use flume;
+use crate::session;
+use crate::status::DeviceStatus;
 use flume::{Receiver, Sender};
-use rand::Rng;
-use serialport::SerialPort;
-use std::ops::{Deref, DerefMut};
+use std::future::Future;
+use std::net::IpAddr;
+use std::ops::{Deref, DerefMut, RangeInclusive};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
 use std::vec::Vec;
 use std::{collections::HashMap, pin::Pin};
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use tokio::net::UdpSocket;
-use tokio::sync::{Mutex, RwLock};
-use tokio_serial::{SerialPortBuilderExt, SerialStream};
-use tracing::{debug, error, info, instrument, trace, warn};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::{oneshot, Mutex, OwnedMutexGuard};
+use tokio_serial::{SerialPort, SerialPortBuilderExt, SerialStream};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, ReceiverStream};
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+use tracing::{debug, error, info, warn};
 
 // a workaround for SerialStream not being Send
 pub struct SyncSerialStream(pub SerialStream);
@@ -39,50 +55,670 @@ pub struct Channel<T> {
 }
 
 pub type BufferType = Vec<u8>;
-pub type PinnedSerialPort = Pin<Arc<Mutex<SyncSerialStream>>>;
-pub struct ManagedSerialDevice {
+/// `None` while the device is unplugged
+pub type PinnedSerialPort = Pin<Arc<Mutex<Option<SyncSerialStream>>>>;
+
+/// How long the reader holds the port before giving the writers a chance
+const READ_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How often an unplugged port is looked for, when it's to be reconnected
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How much is written to the port at once before giving the reader a chance
+const WRITE_SLICE: usize = 4096;
+
+fn disconnected() -> serialport::Error {
+    serialport::Error::new(serialport::ErrorKind::NoDevice, "the port is disconnected")
+}
+
+fn not_connected() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::NotConnected, "the port is disconnected")
+}
+
+/// Wait until what's been written to the port is transmitted (tcdrain).
+/// tokio-serial would drain inside poll_flush, blocking a runtime thread with the port held,
+/// so it's done off the runtime on a duplicate of the descriptor.
+#[cfg(unix)]
+async fn drain(port: &PinnedSerialPort) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, IntoRawFd};
+    let fd = match port.lock().await.as_ref() {
+        // the descriptor is open for as long as the port is held
+        Some(p) => unsafe { BorrowedFd::borrow_raw(p.as_raw_fd()) }.try_clone_to_owned()?,
+        None => return Err(not_connected()),
+    };
+    // closes the duplicate once dropped
+    let mut tty = unsafe { serialport::TTYPort::from_raw_fd(fd.into_raw_fd()) };
+    tokio::task::spawn_blocking(move || tty.flush())
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+#[cfg(not(unix))]
+async fn drain(port: &PinnedSerialPort) -> std::io::Result<()> {
+    match port.lock().await.as_mut() {
+        Some(p) => p.flush().await,
+        None => Err(not_connected()),
+    }
+}
+
+/// A chunk to be written to the serial port.
+/// If `ack` is set, it would be notified once the data is drained to the device.
+/// It's only written if the device isn't leased, or if `lease` is the token of the lease.
+pub struct WriteCommand {
+    pub data: BufferType,
+    pub ack: Option<oneshot::Sender<std::io::Result<()>>>,
+    pub lease: Option<String>,
+}
+
+impl WriteCommand {
+    pub fn new(data: BufferType) -> Self {
+        WriteCommand {
+            data,
+            ack: None,
+            lease: None,
+        }
+    }
+    pub fn with_ack(
+        data: BufferType,
+        lease: Option<String>,
+    ) -> (Self, oneshot::Receiver<std::io::Result<()>>) {
+        let (tx, rx) = oneshot::channel();
+        (
+            WriteCommand {
+                data,
+                ack: Some(tx),
+                lease,
+            },
+            rx,
+        )
+    }
+}
+
+/// Write `cmd` a slice at a time, so that the port isn't held for the whole of a long write
+async fn write_to_port(control: &PortControl, cmd: WriteCommand) {
+    let status = &control.status;
+    if let Err(e) = control.lease.check(cmd.lease.as_deref()) {
+        debug!("not writing to a leased port: {}", e.message());
+        if let Some(ack) = cmd.ack {
+            let e = std::io::Error::new(std::io::ErrorKind::PermissionDenied, e.message());
+            let _ = ack.send(Err(e));
+        }
+        return;
+    }
+    let mut r = Ok(());
+    for slice in cmd.data.chunks(WRITE_SLICE) {
+        r = match control.port.lock().await.as_mut() {
+            Some(port) => port.write_all(slice).await,
+            None => Err(not_connected()),
+        };
+        if r.is_err() {
+            break;
+        }
+    }
+    if r.is_ok() && cmd.ack.is_some() {
+        r = drain(&control.port).await;
+    }
+    match &r {
+        Ok(()) => status.add_written(cmd.data.len()),
+        Err(e) => {
+            error!("error writing to serial port: {}", e);
+            status.write_error(e);
+        }
+    }
+    if let Some(ack) = cmd.ack {
+        let _ = ack.send(r);
+    }
+}
+
+/// Write `data` and wait until it's drained to the device
+pub async fn write_acked(
+    inbound: &Sender<WriteCommand>,
+    device: &str,
+    data: BufferType,
+    lease: Option<String>,
+) -> Result<(), tonic::Status> {
+    let (cmd, ack) = WriteCommand::with_ack(data, lease);
+    let closed = || tonic::Status::aborted(format!("{} was closed", device));
+    inbound.send_async(cmd).await.map_err(|_| closed())?;
+    match ack.await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotConnected => {
+            Err(tonic::Status::unavailable(e.to_string()))
+        }
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            Err(tonic::Status::failed_precondition(e.to_string()))
+        }
+        Ok(Err(e)) => Err(tonic::Status::internal(e.to_string())),
+        Err(_) => Err(closed()),
+    }
+}
+
+/// How many chunks a subscriber could lag behind, unless [api::OpenOptions] says otherwise
+const DEFAULT_SUBSCRIBER_CAPACITY: usize = 8;
+const MAX_SUBSCRIBER_CAPACITY: u32 = 65536;
+
+/// How much is read from the port at once, unless [api::OpenOptions] says otherwise
+const DEFAULT_READ_SIZE: usize = 512;
+const MAX_READ_SIZE: u32 = 1 << 20;
+
+/// A chunk read from the serial port
+#[derive(Clone)]
+pub struct Chunk {
+    /// counted from 0 once the device is opened. A gap means the subscriber has missed some.
+    pub seq: u64,
+    pub received_at: SystemTime,
+    pub data: BufferType,
+}
+
+impl Chunk {
+    pub fn into_api(self, device: &str) -> api::Bytes {
+        api::Bytes {
+            value: self.data,
+            seq: self.seq,
+            timestamp: Some(self.received_at.into()),
+            device: device.to_string(),
+        }
+    }
+}
+
+/// Fan-out of the data coming out of the serial port.
+/// Every subscriber gets its own bounded channel so a slow one won't steal chunks from the others.
+/// What happens when one of them is full is up to the [api::OverflowPolicy].
+pub struct Subscribers {
+    next_id: AtomicU64,
+    next_seq: AtomicU64,
+    capacity: usize,
+    policy: api::OverflowPolicy,
+    /// chunks and bytes dropped for the lagging subscribers
+    dropped_chunks: AtomicU64,
+    dropped_bytes: AtomicU64,
+    /// the receiver is kept to drop the oldest chunk when the subscriber is lagging behind
+    channels: parking_lot::Mutex<HashMap<u64, Channel<Chunk>>>,
+}
+
+impl Subscribers {
+    pub fn new(capacity: usize, policy: api::OverflowPolicy) -> Self {
+        Subscribers {
+            next_id: AtomicU64::new(0),
+            next_seq: AtomicU64::new(0),
+            capacity,
+            policy,
+            dropped_chunks: AtomicU64::new(0),
+            dropped_bytes: AtomicU64::new(0),
+            channels: Default::default(),
+        }
+    }
+    pub fn subscribe(self: &Arc<Self>) -> Subscription {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = flume::bounded::<Chunk>(self.capacity);
+        self.channels.lock().insert(
+            id,
+            Channel {
+                rx: Arc::new(rx.clone()),
+                tx: Arc::new(tx),
+            },
+        );
+        Subscription {
+            id,
+            subscribers: self.clone(),
+            stream: rx.into_stream(),
+        }
+    }
+    /// With [api::OverflowPolicy::Block], wait until every subscriber has room for `data`
+    pub async fn publish(&self, data: &[u8]) {
+        let chunk = Chunk {
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+            received_at: SystemTime::now(),
+            data: data.to_vec(),
+        };
+        if self.policy == api::OverflowPolicy::Block {
+            // the map can't be held across the await
+            let senders: Vec<_> = self
+                .channels
+                .lock()
+                .values()
+                .map(|ch| ch.tx.clone())
+                .collect();
+            for tx in senders {
+                // an error means that it has unsubscribed in the meantime
+                let _ = tx.send_async(chunk.clone()).await;
+            }
+            return;
+        }
+        let channels = self.channels.lock();
+        for (id, ch) in channels.iter() {
+            if let Err(flume::TrySendError::Full(c)) = ch.tx.try_send(chunk.clone()) {
+                if self.policy == api::OverflowPolicy::DropNewest {
+                    debug!(
+                        "subscriber {} is lagging behind, dropping the new chunk",
+                        id
+                    );
+                    self.count_dropped(&c);
+                    continue;
+                }
+                debug!(
+                    "subscriber {} is lagging behind, dropping the oldest chunk",
+                    id
+                );
+                // the subscriber could have made room in the meantime
+                if let Ok(oldest) = ch.rx.try_recv() {
+                    self.count_dropped(&oldest);
+                }
+                if let Err(e) = ch.tx.try_send(c) {
+                    self.count_dropped(&e.into_inner());
+                }
+            }
+        }
+    }
+    fn count_dropped(&self, chunk: &Chunk) {
+        self.dropped_chunks.fetch_add(1, Ordering::Relaxed);
+        self.dropped_bytes
+            .fetch_add(chunk.data.len() as u64, Ordering::Relaxed);
+    }
+    pub fn count(&self) -> usize {
+        self.channels.lock().len()
+    }
+    pub fn dropped_chunks(&self) -> u64 {
+        self.dropped_chunks.load(Ordering::Relaxed)
+    }
+    pub fn dropped_bytes(&self) -> u64 {
+        self.dropped_bytes.load(Ordering::Relaxed)
+    }
+    /// Drop every sender so the subscriptions end after the buffered data is consumed
+    pub fn clear(&self) {
+        self.channels.lock().clear();
+    }
+}
+
+/// A stream of chunks read from the serial port. Unsubscribes on drop.
+pub struct Subscription {
+    id: u64,
+    subscribers: Arc<Subscribers>,
+    stream: flume::r#async::RecvStream<'static, Chunk>,
+}
+
+impl Stream for Subscription {
+    type Item = Chunk;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.stream).poll_next(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.subscribers.channels.lock().remove(&self.id);
+    }
+}
+
+/// A stream that ends on shutdown, for those that don't end with the devices
+struct UntilShutdown<T> {
+    stream: Pin<Box<dyn Stream<Item = T> + Send>>,
+    shutdown: Pin<Box<WaitForCancellationFutureOwned>>,
+}
+
+impl<T> Stream for UntilShutdown<T> {
+    type Item = T;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.shutdown.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(None);
+        }
+        self.stream.as_mut().poll_next(cx)
+    }
+}
+
+/// Shared access to the live serial port and the options it's running with,
+/// for whoever needs to control the port besides reading and writing it.
+#[derive(Clone)]
+pub struct PortControl {
     port: PinnedSerialPort,
+    options: Arc<parking_lot::Mutex<api::ManagedOptions>>,
+    /// DTR, RTS and break as last set, since they can't be read back from the port
+    output_lines: Arc<parking_lot::Mutex<OutputLines>>,
+    status: Arc<DeviceStatus>,
+    lease: Arc<LeaseSlot>,
+}
+
+#[derive(Clone, Copy)]
+pub struct OutputLines {
+    pub dtr: bool,
+    pub rts: bool,
+    pub break_on: bool,
+}
+
+impl Default for OutputLines {
+    /// DTR and RTS are asserted when a tty is opened
+    fn default() -> Self {
+        OutputLines {
+            dtr: true,
+            rts: true,
+            break_on: false,
+        }
+    }
+}
+
+impl PortControl {
+    pub fn managed_options(&self) -> api::ManagedOptions {
+        self.options.lock().clone()
+    }
+    /// The serial settings currently applied to the port
+    pub fn options(&self) -> api::OpenOptions {
+        self.options.lock().options.clone().unwrap_or_default()
+    }
+    /// Apply the serial settings of `options` to the live port.
+    /// The bridge settings (UDP/TCP...) can't be changed and are kept as is.
+    pub async fn configure(&self, options: &api::OpenOptions) -> Result<(), serialport::Error> {
+        let previous = self.options();
+        self.with_port(|p| match apply_options(p, options) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                // back to what's reported, rather than half of the new settings
+                if let Err(e) = apply_options(p, &previous) {
+                    error!("error restoring the serial settings: {}", e);
+                }
+                Err(e)
+            }
+        })
+        .await?;
+        let mut managed = self.options.lock();
+        let current = managed.options.get_or_insert_with(Default::default);
+        current.baud = options.baud;
+        current.data_bits = options.data_bits;
+        current.flow_control = options.flow_control;
+        current.parity = options.parity;
+        current.stop_bits = options.stop_bits;
+        current.timeout = options.timeout.clone();
+        Ok(())
+    }
+    /// Set DTR then RTS, leaving the ones that are `None` as they are
+    pub async fn set_modem_lines(
+        &self,
+        dtr: Option<bool>,
+        rts: Option<bool>,
+    ) -> Result<(), serialport::Error> {
+        self.with_port(|port| {
+            if let Some(dtr) = dtr {
+                port.write_data_terminal_ready(dtr)?;
+                self.output_lines.lock().dtr = dtr;
+            }
+            if let Some(rts) = rts {
+                port.write_request_to_send(rts)?;
+                self.output_lines.lock().rts = rts;
+            }
+            Ok(())
+        })
+        .await
+    }
+    /// Set or clear the break condition
+    pub async fn set_break(&self, on: bool) -> Result<(), serialport::Error> {
+        self.with_port(|port| {
+            if on {
+                port.set_break()?;
+            } else {
+                port.clear_break()?;
+            }
+            self.output_lines.lock().break_on = on;
+            Ok(())
+        })
+        .await
+    }
+    /// Assert the break for `duration`. The port isn't locked in the meantime,
+    /// so it keeps being read.
+    pub async fn send_break(&self, duration: Duration) -> Result<(), serialport::Error> {
+        self.set_break(true).await?;
+        // cleared by a task of its own, in case the caller goes away in the meantime
+        let control = self.clone();
+        let clear = tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            control.set_break(false).await
+        });
+        clear.await.unwrap_or_else(|e| {
+            Err(serialport::Error::new(
+                serialport::ErrorKind::Unknown,
+                e.to_string(),
+            ))
+        })
+    }
+    /// A timed break if there's a duration, otherwise set or clear it as [api::BreakRequest] says
+    pub async fn break_request(
+        &self,
+        duration: &Option<prost_types::Duration>,
+        on: bool,
+    ) -> Result<(), tonic::Status> {
+        match api_positive_duration_2_std(duration, "duration")? {
+            Some(duration) => self.send_break(duration).await,
+            None => self.set_break(on).await,
+        }
+        .map_err(sp_error_2_status)
+    }
+    pub fn status(&self) -> &DeviceStatus {
+        &self.status
+    }
+    pub fn lease(&self) -> &LeaseSlot {
+        &self.lease
+    }
+    pub fn device_status(&self, device: &str) -> api::DeviceStatus {
+        self.status.to_api(device, self.lease.holder())
+    }
+    pub fn output_lines(&self) -> OutputLines {
+        *self.output_lines.lock()
+    }
+    /// Read CTS/DSR/RI/CD along with the last DTR/RTS that was set
+    pub async fn modem_lines(&self) -> Result<api::ModemLines, serialport::Error> {
+        let output = self.output_lines();
+        self.with_port(|port| {
+            Ok(api::ModemLines {
+                cts: port.read_clear_to_send()?,
+                dsr: port.read_data_set_ready()?,
+                ri: port.read_ring_indicator()?,
+                cd: port.read_carrier_detect()?,
+                dtr: output.dtr,
+                rts: output.rts,
+            })
+        })
+        .await
+    }
+    /// Run `f` with the port locked, e.g. to reach the methods of [SerialPort].
+    /// Fails without running it if the port is disconnected.
+    pub async fn with_port<R>(
+        &self,
+        f: impl FnOnce(&mut SerialStream) -> serialport::Result<R>,
+    ) -> serialport::Result<R> {
+        match self.port.lock().await.as_mut() {
+            Some(port) => f(&mut port.0),
+            None => Err(disconnected()),
+        }
+    }
+}
+
+pub struct ManagedSerialDevice {
     port_name: String,
-    options: api::ManagedOptions,
-    udp: Option<Arc<Mutex<UdpSocket>>>,
+    control: PortControl,
+    udp: Option<Arc<UdpSocket>>,
+    udp_peers: bridge::udp::Peers,
+    /// tasks of the UDP/TCP/RFC 2217 bridges
+    bridge_handles: Vec<tokio::task::JoinHandle<()>>,
     outbound_handle: tokio::task::JoinHandle<()>,
     inbound_handle: tokio::task::JoinHandle<()>,
+    modem_handle: tokio::task::JoinHandle<()>,
+    modem: modem::Watcher,
+    /// cancelled on close. The inbound task would drain what's left in `inbound` before exiting.
+    cancel: CancellationToken,
     /// outbound refers to data going from the serial port to the outside world.
-    /// [Reader] i.e. `rx` is expected to be used. Don't touch `tx`.
-    outbound: Channel<BufferType>,
+    /// Call [ManagedSerialDevice::subscribe] to get a stream of it.
+    outbound: Arc<Subscribers>,
     /// inbound refers to data coming from the outside world to the serial port
     /// [Writer] i.e. `tx` is expected to be used. Don't touch `rx`.
-    inbound: Channel<BufferType>,
+    inbound: Channel<WriteCommand>,
 }
 
 impl ManagedSerialDevice {
-    pub fn outbound(&self) -> Arc<Receiver<BufferType>> {
-        self.outbound.rx.clone()
+    pub fn subscribe(&self) -> Subscription {
+        self.outbound.subscribe()
     }
-    pub fn inbound(&self) -> Arc<Sender<BufferType>> {
+    pub fn inbound(&self) -> Arc<Sender<WriteCommand>> {
         self.inbound.tx.clone()
     }
     pub fn port_name(&self) -> &str {
         &self.port_name
     }
-    pub fn udp(&self) -> Option<Arc<Mutex<UdpSocket>>> {
-        self.udp
+    pub fn options(&self) -> api::ManagedOptions {
+        self.control.managed_options()
+    }
+    pub fn status(&self) -> api::DeviceStatus {
+        self.control.device_status(&self.port_name)
+    }
+    pub fn stats(&self) -> api::DeviceStats {
+        self.control.status.to_api_stats(
+            &self.port_name,
+            self.outbound.dropped_chunks(),
+            self.outbound.dropped_bytes(),
+        )
     }
-    pub fn options(&self) -> &api::ManagedOptions {
-        &self.options
+    /// The Read streams and the bridges (one per TCP/RFC 2217 client) receiving the outbound data
+    pub fn subscribers(&self) -> usize {
+        self.outbound.count()
+    }
+    pub fn udp_peers(&self) -> usize {
+        self.udp_peers.count()
+    }
+    /// Whether nobody is reading from it. The UDP bridge is always subscribed, its peers count instead.
+    pub fn idle(&self) -> bool {
+        self.outbound.count() <= usize::from(self.udp.is_some()) && self.udp_peers.count() == 0
+    }
+    pub fn control(&self) -> PortControl {
+        self.control.clone()
+    }
+    pub fn modem_watcher(&self) -> modem::Watcher {
+        self.modem.clone()
+    }
+    /// Stop reading, flush the pending writes to the port and release everything
+    /// (tasks, channels, the UDP socket and TCP listeners) held by the device.
+    pub async fn close(mut self) {
+        self.control.status.set_state(api::DeviceState::Closing);
+        self.outbound_handle.abort();
+        self.modem_handle.abort();
+        self.cancel.cancel();
+        if let Err(e) = (&mut self.inbound_handle).await {
+            if !e.is_cancelled() {
+                error!("inbound task for {} failed: {}", self.port_name(), e);
+            }
+        }
+        match drain(&self.control.port).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotConnected => {}
+            Err(e) => error!("error flushing {}: {}", self.port_name(), e),
+        }
+        // the sessions and bridges still holding a PortControl mustn't keep the port open
+        self.control.port.lock().await.take();
+        // ends the Read streams
+        self.outbound.clear();
+        // the sockets are closed once the last reference is dropped
+        for h in self.bridge_handles.drain(..) {
+            h.abort();
+        }
+        self.udp.take();
+        info!("closed {}", self.port_name());
     }
 }
 
 impl Drop for ManagedSerialDevice {
     fn drop(&mut self) {
-        let _ = self.outbound_handle.abort();
-        let _ = self.inbound_handle.abort();
+        self.outbound_handle.abort();
+        self.inbound_handle.abort();
+        self.modem_handle.abort();
+        for h in &self.bridge_handles {
+            h.abort();
+        }
     }
 }
 
+pub type ManagedDevices = Arc<Mutex<HashMap<String, ManagedSerialDevice>>>;
+
+/// The devices being opened. Opening one takes a while and the port can only be opened once,
+/// so the Opens of a device run one after the other, the later ones attaching to it.
 #[derive(Default)]
+struct Opening(parking_lot::Mutex<HashMap<String, Arc<Mutex<()>>>>);
+
+impl Opening {
+    /// Wait for whoever is opening `device`, then hold it until the guard is dropped
+    async fn lock(self: &Arc<Self>, device: &str) -> OpeningGuard {
+        let lock = self.0.lock().entry(device.to_string()).or_default().clone();
+        OpeningGuard {
+            guard: Some(lock.lock_owned().await),
+            opening: self.clone(),
+            device: device.to_string(),
+        }
+    }
+}
+
+struct OpeningGuard {
+    guard: Option<OwnedMutexGuard<()>>,
+    opening: Arc<Opening>,
+    device: String,
+}
+
+impl Drop for OpeningGuard {
+    fn drop(&mut self) {
+        self.guard.take();
+        let mut opening = self.opening.0.lock();
+        // unless someone else is waiting for it
+        if opening
+            .get(&self.device)
+            .is_some_and(|l| Arc::strong_count(l) == 1)
+        {
+            opening.remove(&self.device);
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct SerialServer {
-    managed: Arc<Mutex<HashMap<String, ManagedSerialDevice>>>,
+    managed: ManagedDevices,
+    config: Arc<Config>,
+    devices: hotplug::Watcher,
+    opening: Arc<Opening>,
+    /// cancelled by [SerialServer::close_all]
+    shutdown: CancellationToken,
+}
+
+impl SerialServer {
+    /// Needs a runtime, for the hotplug watcher
+    pub fn new(config: Config) -> Self {
+        let managed = ManagedDevices::default();
+        let (devices, _) = hotplug::spawn_watcher(managed.clone());
+        SerialServer {
+            managed,
+            config: Arc::new(config),
+            devices,
+            opening: Default::default(),
+            shutdown: CancellationToken::new(),
+        }
+    }
+    /// Run `f` with the managed `device`, without holding the map any longer
+    async fn with_device<R>(
+        &self,
+        device: &str,
+        f: impl FnOnce(&ManagedSerialDevice) -> R,
+    ) -> Result<R, tonic::Status> {
+        let managed = self.managed.lock().await;
+        let dev = managed
+            .get(device)
+            .ok_or_else(|| tonic::Status::not_found(format!("{} is not managed", device)))?;
+        Ok(f(dev))
+    }
+    pub fn managed_devices(&self) -> ManagedDevices {
+        self.managed.clone()
+    }
+    /// Close every managed device, flushing their pending writes,
+    /// and end the streams that aren't tied to any of them
+    pub async fn close_all(&self) {
+        self.shutdown.cancel();
+        let devices: Vec<_> = self.managed.lock().await.drain().map(|(_, d)| d).collect();
+        for dev in devices {
+            dev.close().await;
+        }
+    }
 }
 
 fn api_raw_parity_2_sp(parity: i32) -> Option<serialport::Parity> {
@@ -125,24 +761,418 @@ fn api_raw_stop_bit_2_sp(stop_bits: i32) -> Option<serialport::StopBits> {
     .ok()
 }
 
+pub fn sp_port_info_2_api(info: serialport::SerialPortInfo) -> api::Serial {
+    let (port_type, usb) = sp_port_type_2_api(info.port_type);
+    api::Serial {
+        device: info.port_name,
+        port_type: port_type as i32,
+        usb,
+        ..Default::default()
+    }
+}
+
+fn sp_port_type_2_api(
+    port_type: serialport::SerialPortType,
+) -> (api::PortType, Option<api::UsbInfo>) {
+    match port_type {
+        serialport::SerialPortType::UsbPort(info) => (
+            api::PortType::Usb,
+            Some(api::UsbInfo {
+                vid: info.vid.into(),
+                pid: info.pid.into(),
+                serial_number: info.serial_number,
+                manufacturer: info.manufacturer,
+                product: info.product,
+            }),
+        ),
+        serialport::SerialPortType::PciPort => (api::PortType::Pci, None),
+        serialport::SerialPortType::BluetoothPort => (api::PortType::Bluetooth, None),
+        serialport::SerialPortType::Unknown => (api::PortType::UnknownPort, None),
+    }
+}
+
+pub fn api_timeout_2_std(timeout: &Option<prost_types::Duration>) -> Duration {
+    let prost_timeout = timeout.clone().unwrap_or_default();
+    Duration::from_nanos(prost_timeout.nanos as u64)
+        + Duration::from_secs(prost_timeout.seconds as u64)
+}
+
+/// An optional duration of a request, which must be positive if set
+pub fn api_positive_duration_2_std(
+    duration: &Option<prost_types::Duration>,
+    name: &str,
+) -> Result<Option<Duration>, tonic::Status> {
+    match duration {
+        Some(d) if d.seconds < 0 || d.nanos < 0 || (d.seconds == 0 && d.nanos == 0) => Err(
+            tonic::Status::invalid_argument(format!("{} must be positive", name)),
+        ),
+        Some(_) => Ok(Some(api_timeout_2_std(duration))),
+        None => Ok(None),
+    }
+}
+
+/// A size or count of a request, `default` if it's 0
+pub fn api_count_2_usize(
+    count: u32,
+    default: usize,
+    max: u32,
+    name: &str,
+) -> Result<usize, tonic::Status> {
+    match count {
+        0 => Ok(default),
+        n if n <= max => Ok(n as usize),
+        n => Err(tonic::Status::invalid_argument(format!(
+            "{} {} is over {}",
+            name, n, max
+        ))),
+    }
+}
+
+/// Open `device` with the serial settings of `options`
+fn open_port(device: &str, options: &api::OpenOptions) -> serialport::Result<SerialStream> {
+    let settings = SpSettings::from_api(options);
+    serialport::new(device, options.baud)
+        .data_bits(settings.data_bits)
+        .parity(settings.parity)
+        .stop_bits(settings.stop_bits)
+        .flow_control(settings.flow_control)
+        .timeout(settings.timeout)
+        .open_native_async()
+}
+
+async fn usb_serial_number(device: &str) -> Option<String> {
+    let ports = hotplug::available_ports().await.ok()?;
+    ports.get(device)?.usb.as_ref()?.serial_number.clone()
+}
+
+/// Publish what's read from the port until it's unplugged.
+/// If `reconnect` is set in its options, wait for it to come back and carry on.
+async fn read_port(
+    device: String,
+    usb_serial_number: Option<String>,
+    read_size: usize,
+    control: PortControl,
+    outbound: Arc<Subscribers>,
+) {
+    let mut buf = vec![0u8; read_size];
+    loop {
+        // https://v0-1--tokio.netlify.app/docs/io/async_read_write/
+        let r = {
+            let mut port = control.port.lock().await;
+            match port.as_mut() {
+                Some(port) => tokio::time::timeout(READ_POLL_INTERVAL, port.read(&mut buf)).await,
+                None => Ok(Err(disconnected().into())),
+            }
+        };
+        let r = match r {
+            Ok(r) => r,
+            // nothing to read. release the lock for the writers
+            Err(_) => continue,
+        };
+        let e = match r {
+            Ok(0) => std::io::Error::from(std::io::ErrorKind::UnexpectedEof),
+            Ok(n) => {
+                control.status.add_read(n);
+                outbound.publish(&buf[0..n]).await;
+                continue;
+            }
+            Err(e) => e,
+        };
+        // drop the file so the device node could be reused once it's plugged back
+        control.port.lock().await.take();
+        let state = if is_disconnection(&e) {
+            api::DeviceState::Disconnected
+        } else {
+            api::DeviceState::Failed
+        };
+        control.status.read_error(state, &e);
+        if !control.options().reconnect {
+            warn!("{} is {:?}: {}", device, state, e);
+            return;
+        }
+        warn!("{} is {:?}, trying to reopen it: {}", device, state, e);
+        control.status.set_state(api::DeviceState::Reconnecting);
+        let port = reopen(&device, usb_serial_number.as_deref(), &control).await;
+        *control.port.lock().await = Some(SyncSerialStream(port));
+        control.status.set_state(api::DeviceState::Open);
+        info!("{} is reconnected", device);
+    }
+}
+
+/// Whether a read error means that the device is gone, rather than broken
+fn is_disconnection(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    if matches!(
+        e.kind(),
+        ErrorKind::UnexpectedEof | ErrorKind::BrokenPipe | ErrorKind::NotConnected
+    ) {
+        return true;
+    }
+    // EIO, ENXIO and ENODEV, as a tty reports a hangup
+    cfg!(unix) && matches!(e.raw_os_error(), Some(5 | 6 | 19))
+}
+
+/// Wait until the device could be opened again, with the options and lines it had
+async fn reopen(
+    device: &str,
+    usb_serial_number: Option<&str>,
+    control: &PortControl,
+) -> SerialStream {
+    loop {
+        tokio::time::sleep(RECONNECT_INTERVAL).await;
+        let path = match usb_serial_number {
+            Some(serial) => {
+                let Ok(ports) = hotplug::available_ports().await else {
+                    continue;
+                };
+                let found = ports.into_values().find(|p| {
+                    p.usb.as_ref().and_then(|u| u.serial_number.as_deref()) == Some(serial)
+                });
+                match found {
+                    Some(p) => p.device,
+                    None => continue,
+                }
+            }
+            None => device.to_string(),
+        };
+        let lines = control.output_lines();
+        // DTR and RTS are asserted on open already
+        let r = open_port(&path, &control.options()).and_then(|mut port| {
+            if !lines.dtr {
+                port.write_data_terminal_ready(false)?;
+            }
+            if !lines.rts {
+                port.write_request_to_send(false)?;
+            }
+            if lines.break_on {
+                port.set_break()?;
+            }
+            Ok(port)
+        });
+        match r {
+            Ok(port) => {
+                if path != device {
+                    info!("{} is back as {}", device, path);
+                }
+                return port;
+            }
+            Err(e) => debug!("error reopening {}: {}", path, e),
+        }
+    }
+}
+
+/// The serial settings of [api::OpenOptions] but the baud rate, with defaults for the invalid ones
+struct SpSettings {
+    data_bits: serialport::DataBits,
+    parity: serialport::Parity,
+    stop_bits: serialport::StopBits,
+    flow_control: serialport::FlowControl,
+    timeout: Duration,
+}
+
+impl SpSettings {
+    fn from_api(options: &api::OpenOptions) -> Self {
+        SpSettings {
+            data_bits: api_raw_data_bits_2_sp(options.data_bits)
+                .unwrap_or(serialport::DataBits::Eight),
+            parity: api_raw_parity_2_sp(options.parity).unwrap_or(serialport::Parity::None),
+            stop_bits: api_raw_stop_bit_2_sp(options.stop_bits)
+                .unwrap_or(serialport::StopBits::One),
+            flow_control: api_raw_flow_2_sp(options.flow_control)
+                .unwrap_or(serialport::FlowControl::None),
+            timeout: api_timeout_2_std(&options.timeout),
+        }
+    }
+}
+
+/// Reconfigure an opened port, with the same defaults as [SerialServer::open]
+fn apply_options(port: &mut dyn SerialPort, options: &api::OpenOptions) -> serialport::Result<()> {
+    let settings = SpSettings::from_api(options);
+    port.set_baud_rate(options.baud)?;
+    port.set_data_bits(settings.data_bits)?;
+    port.set_parity(settings.parity)?;
+    port.set_stop_bits(settings.stop_bits)?;
+    port.set_flow_control(settings.flow_control)?;
+    port.set_timeout(settings.timeout)?;
+    Ok(())
+}
+
+pub fn sp_error_2_status(e: serialport::Error) -> tonic::Status {
+    match e.kind {
+        serialport::ErrorKind::NoDevice => tonic::Status::unavailable(e.description),
+        serialport::ErrorKind::InvalidInput => tonic::Status::invalid_argument(e.description),
+        _ => tonic::Status::internal(e.description),
+    }
+}
+
+/// An empty address means the server default
+fn parse_bind_address(address: &str, default: IpAddr) -> Result<IpAddr, tonic::Status> {
+    if address.is_empty() {
+        return Ok(default);
+    }
+    address
+        .parse()
+        .map_err(|_| tonic::Status::invalid_argument(format!("invalid bind address {}", address)))
+}
+
+fn parse_port(port: u32) -> Result<u16, tonic::Status> {
+    u16::try_from(port)
+        .map_err(|_| tonic::Status::invalid_argument(format!("invalid port {}", port)))
+}
+
+async fn listen_tcp(
+    ip: IpAddr,
+    port: u16,
+    range: &RangeInclusive<u16>,
+) -> Result<TcpListener, tonic::Status> {
+    bridge::bind_in_range(ip, port, range, TcpListener::bind)
+        .await
+        .map_err(|e| {
+            tonic::Status::unavailable(format!("error listening on TCP {}:{}: {}", ip, port, e))
+        })
+}
+
+/// Attach one more client to a device that's already managed, as long as `options` match
+fn attach(
+    dev: &ManagedSerialDevice,
+    options: &api::OpenOptions,
+) -> Result<api::Serial, tonic::Status> {
+    let managed = dev.options();
+    if let Some(option) = mismatched_option(&managed, options) {
+        return Err(tonic::Status::already_exists(format!(
+            "{} is already open with another {}",
+            dev.port_name(),
+            option
+        )));
+    }
+    let clients = dev.control.status.attach();
+    info!("{} attached, {} clients", dev.port_name(), clients);
+    Ok(api::Serial {
+        device: dev.port_name().to_string(),
+        managed: Some(managed),
+        status: Some(dev.status()),
+        ..Default::default()
+    })
+}
+
+/// The first of `requested` that differs from the options of a managed device, if any.
+/// Unset ports and bind addresses match whatever the device got.
+fn mismatched_option(
+    current: &api::ManagedOptions,
+    requested: &api::OpenOptions,
+) -> Option<&'static str> {
+    let opened = current.options.clone().unwrap_or_default();
+    let or_default = |n: u32, default: usize| if n == 0 { default } else { n as usize };
+    let address = |requested: &str, actual: &str| requested.is_empty() || requested == actual;
+    let port =
+        |requested: u32, actual: i32| requested == 0 || i64::from(requested) == i64::from(actual);
+    let checks = [
+        ("baud rate", opened.baud == requested.baud),
+        ("data bits", opened.data_bits == requested.data_bits),
+        (
+            "flow control",
+            opened.flow_control == requested.flow_control,
+        ),
+        ("parity", opened.parity == requested.parity),
+        ("stop bits", opened.stop_bits == requested.stop_bits),
+        ("timeout", opened.timeout == requested.timeout),
+        (
+            "UDP bind address",
+            address(&requested.udp_bind_address, &current.udp_bind_address),
+        ),
+        ("UDP port", port(requested.udp_port, current.udp_port)),
+        ("TCP listener", opened.tcp == requested.tcp),
+        (
+            "TCP bind address",
+            !requested.tcp || address(&requested.tcp_bind_address, &current.tcp_bind_address),
+        ),
+        (
+            "TCP port",
+            !requested.tcp || port(requested.tcp_port, current.tcp_port),
+        ),
+        ("RFC 2217 listener", opened.rfc2217 == requested.rfc2217),
+        (
+            "RFC 2217 bind address",
+            !requested.rfc2217
+                || address(
+                    &requested.rfc2217_bind_address,
+                    &current.rfc2217_bind_address,
+                ),
+        ),
+        (
+            "RFC 2217 port",
+            !requested.rfc2217 || port(requested.rfc2217_port, current.rfc2217_port),
+        ),
+        ("reconnect", opened.reconnect == requested.reconnect),
+        (
+            "overflow policy",
+            opened.overflow_policy == requested.overflow_policy,
+        ),
+        (
+            "reader capacity",
+            or_default(opened.reader_capacity, DEFAULT_SUBSCRIBER_CAPACITY)
+                == or_default(requested.reader_capacity, DEFAULT_SUBSCRIBER_CAPACITY),
+        ),
+        (
+            "read size",
+            or_default(opened.read_size, DEFAULT_READ_SIZE)
+                == or_default(requested.read_size, DEFAULT_READ_SIZE),
+        ),
+        (
+            "idle timeout",
+            opened.idle_timeout == requested.idle_timeout,
+        ),
+    ];
+    checks
+        .iter()
+        .find(|(_, same)| !same)
+        .map(|(option, _)| *option)
+}
+
+/// The port and address for [api::ManagedOptions], port is -1 if not listening
+fn listening_on(listener: Option<&TcpListener>) -> (i32, String) {
+    match listener.and_then(|l| l.local_addr().ok()) {
+        Some(addr) => (addr.port() as i32, addr.ip().to_string()),
+        None => (-1, String::new()),
+    }
+}
+
 // https://github.com/hyperium/tonic/blob/master/examples/routeguide-tutorial.md
 #[tonic::async_trait]
 impl service::SerialService for SerialServer {
-    type ReadStream = tonic::codec::Streaming<api::Bytes>;
+    type ReadStream = Pin<Box<dyn Stream<Item = Result<api::Bytes, tonic::Status>> + Send>>;
+    type SessionStream =
+        Pin<Box<dyn Stream<Item = Result<api::ServerMessage, tonic::Status>> + Send>>;
+    type WatchDevicesStream =
+        Pin<Box<dyn Stream<Item = Result<api::DeviceEvent, tonic::Status>> + Send>>;
+    type WatchModemLinesStream =
+        Pin<Box<dyn Stream<Item = Result<api::ModemLinesEvent, tonic::Status>> + Send>>;
     async fn list(
         &self,
         _req: tonic::Request<()>,
     ) -> Result<tonic::Response<api::ListResponse>, tonic::Status> {
         let mut response = api::ListResponse::default();
-        match serialport::available_ports() {
+        match hotplug::available_ports().await {
             Ok(ports) => {
-                for port in ports {
-                    let managed = self.managed.lock().await;
-                    let options = managed.get(&port.port_name).map(|s| s.options.clone());
-                    let mut s = api::Serial::default();
-                    s.device = port.port_name;
-                    s.managed = options;
-                    response.serials.push(s);
+                let managed = self.managed.lock().await;
+                let mut ports: Vec<_> = ports.into_iter().collect();
+                ports.sort_by(|(a, _), (b, _)| a.cmp(b));
+                for (name, mut serial) in ports {
+                    let dev = managed.get(&name);
+                    serial.managed = dev.map(|d| d.options());
+                    serial.status = dev.map(|d| d.status());
+                    response.serials.push(serial);
+                }
+                // the unplugged ones and the ones the OS doesn't list, e.g. ptys
+                for (name, dev) in managed.iter() {
+                    if !response.serials.iter().any(|s| &s.device == name) {
+                        response.serials.push(api::Serial {
+                            device: name.clone(),
+                            managed: Some(dev.options()),
+                            status: Some(dev.status()),
+                            ..Default::default()
+                        });
+                    }
                 }
                 Ok(tonic::Response::new(response))
             }
@@ -162,108 +1192,179 @@ impl service::SerialService for SerialServer {
             return Err(tonic::Status::invalid_argument("options must be specified"));
         }
         let options = options.unwrap();
-        let parity = api_raw_parity_2_sp(options.parity).unwrap_or(serialport::Parity::None);
-        let stop = api_raw_stop_bit_2_sp(options.stop_bits).unwrap_or(serialport::StopBits::One);
-        let flow = api_raw_flow_2_sp(options.flow_control).unwrap_or(serialport::FlowControl::None);
-        let data = api_raw_data_bits_2_sp(options.data_bits).unwrap_or(serialport::DataBits::Eight);
-        let prost_timeout = options.timeout.clone().unwrap_or_default();
-        let timeout = std::time::Duration::from_nanos(prost_timeout.nanos as u64)
-            + std::time::Duration::from_secs(prost_timeout.seconds as u64);
-        let res = serialport::new(&req.device, options.baud)
-            .data_bits(data)
-            .parity(parity)
-            .stop_bits(stop)
-            .flow_control(flow)
-            .timeout(timeout)
-            .open_native_async();
-        match res {
+        let device = match &req.selector {
+            Some(_) if !req.device.is_empty() => {
+                return Err(tonic::Status::invalid_argument(
+                    "either a device or a selector must be specified, not both",
+                ));
+            }
+            Some(selector) => {
+                let device = Selector::from_api(selector, &self.config)?
+                    .resolve()
+                    .await?;
+                debug!("selector resolved to {}", device);
+                device
+            }
+            None => req.device,
+        };
+        let udp_ip = parse_bind_address(&options.udp_bind_address, self.config.udp_bind_address())?;
+        let udp_port = parse_port(options.udp_port)?;
+        let tcp_ip = parse_bind_address(&options.tcp_bind_address, self.config.tcp_bind_address())?;
+        let tcp_port = parse_port(options.tcp_port)?;
+        let rfc2217_ip = parse_bind_address(
+            &options.rfc2217_bind_address,
+            self.config.rfc2217_bind_address(),
+        )?;
+        let rfc2217_port = parse_port(options.rfc2217_port)?;
+        let policy = api::OverflowPolicy::try_from(options.overflow_policy)
+            .map_err(|_| tonic::Status::invalid_argument("invalid overflow policy"))?;
+        let capacity = api_count_2_usize(
+            options.reader_capacity,
+            DEFAULT_SUBSCRIBER_CAPACITY,
+            MAX_SUBSCRIBER_CAPACITY,
+            "reader capacity",
+        )?;
+        let read_size = api_count_2_usize(
+            options.read_size,
+            DEFAULT_READ_SIZE,
+            MAX_READ_SIZE,
+            "read size",
+        )?;
+        let idle_timeout = api_positive_duration_2_std(&options.idle_timeout, "idle timeout")?;
+        // held until the device is managed, or failed to open
+        let _opening = self.opening.lock(&device).await;
+        if let Some(dev) = self.managed.lock().await.get(&device) {
+            return attach(dev, &options).map(tonic::Response::new);
+        }
+        // matched on reconnection, in case the adapter comes back under another name
+        let usb_serial_number = if options.reconnect {
+            usb_serial_number(&device).await
+        } else {
+            None
+        };
+        match open_port(&device, &options) {
             Ok(port) => {
                 // https://github.com/tokio-rs/tokio/blob/master/examples/echo-udp.rs
-                // https://en.wikipedia.org/wiki/Registered_port
-                // https://stackoverflow.com/questions/67443847/how-to-generate-random-numbers-in-async-rust
-                let udp_port = {
-                    let mut rng = rand::thread_rng();
-                    rng.gen_range(49152..65535)
+                let range = &self.config.udp.port_range;
+                let socket =
+                    match bridge::bind_in_range(udp_ip, udp_port, range, UdpSocket::bind).await {
+                        Ok(s) => Some(Arc::new(s)),
+                        // the caller asked for this very port or address, don't go on without it
+                        Err(e) if udp_port != 0 || !options.udp_bind_address.is_empty() => {
+                            return Err(tonic::Status::unavailable(format!(
+                                "error binding UDP {}:{}: {}",
+                                udp_ip, udp_port, e
+                            )));
+                        }
+                        Err(e) => {
+                            error!("error binding UDP on {}: {}", udp_ip, e);
+                            None
+                        }
+                    };
+                let tcp = if options.tcp {
+                    let range = &self.config.tcp.port_range;
+                    Some(listen_tcp(tcp_ip, tcp_port, range).await?)
+                } else {
+                    None
+                };
+                let rfc2217 = if options.rfc2217 {
+                    let range = &self.config.rfc2217.port_range;
+                    Some(listen_tcp(rfc2217_ip, rfc2217_port, range).await?)
+                } else {
+                    None
                 };
-                // TODO: bind to a random port and listen for incoming data
-                // bind the udp to outbound rx and inbound tx
-                let udp_addr = format!("0.0.0.0:{}", udp_port);
-                let socket = UdpSocket::bind(udp_addr)
-                    .await
-                    .map(|s| Arc::new(Mutex::new(s)))
-                    .map_err(|e| Arc::new(e)); // need an Arc to make error Clone
-                let socket_ = socket.clone();
                 let mut managed_options = api::ManagedOptions::default();
-                // https://github.com/tokio-rs/tokio/discussions/3891
-                let (tx, rx) = flume::bounded::<BufferType>(8);
-                let out_tx = Arc::new(tx);
-                let out_rx = Arc::new(rx);
+                let outbound = Arc::new(Subscribers::new(capacity, policy));
                 managed_options.options = Some(options.clone());
-                managed_options.udp_port = if socket.is_ok() { udp_port } else { -1 };
-                let pinned_port = Arc::pin(Mutex::new(SyncSerialStream(port)));
-                let pinned_port_ = pinned_port.clone();
-                let out_tx_ = out_tx.clone();
-                let out_rx_ = out_rx.clone();
-                let out_handle = tokio::spawn(async move {
-                    let mut buf = [0u8; 512];
-                    // https://v0-1--tokio.netlify.app/docs/io/async_read_write/
-                    loop {
-                        let r = pinned_port_.lock().await.read(&mut buf).await;
-                        match r {
-                            Ok(n) => {
-                                let mut v = std::vec::Vec::with_capacity(n);
-                                v.extend_from_slice(&buf[0..n]);
-                                if let Some(mut c) = out_rx_.capacity() {
-                                    while c >= out_tx_.len() {
-                                        debug!("full channel for {} >= {}", out_tx_.len(), c);
-                                        let _ = out_rx_.recv_async().await;
-                                        c = out_rx_.capacity().unwrap_or(0);
-                                    }
-                                } else {
-                                    debug!("unbounded channel");
-                                }
-                                match out_tx_.send(v) {
-                                    Ok(_) => {}
-                                    Err(e) => {
-                                        error!("error sending to channel: {}", e);
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                error!("error reading from serial port: {}", e);
-                            }
-                        }
+                match socket.as_ref().and_then(|s| s.local_addr().ok()) {
+                    Some(addr) => {
+                        managed_options.udp_port = addr.port() as i32;
+                        managed_options.udp_bind_address = addr.ip().to_string();
                     }
-                });
-                let (in_tx, in_rx) = flume::bounded::<BufferType>(8);
+                    None => managed_options.udp_port = -1,
+                }
+                (managed_options.tcp_port, managed_options.tcp_bind_address) =
+                    listening_on(tcp.as_ref());
+                (
+                    managed_options.rfc2217_port,
+                    managed_options.rfc2217_bind_address,
+                ) = listening_on(rfc2217.as_ref());
+                let pinned_port = Arc::pin(Mutex::new(Some(SyncSerialStream(port))));
+                let control = PortControl {
+                    port: pinned_port.clone(),
+                    options: Arc::new(parking_lot::Mutex::new(managed_options.clone())),
+                    output_lines: Default::default(),
+                    status: Default::default(),
+                    lease: Default::default(),
+                };
+                let out_handle = tokio::spawn(read_port(
+                    device.clone(),
+                    usb_serial_number,
+                    read_size,
+                    control.clone(),
+                    outbound.clone(),
+                ));
+                let (in_tx, in_rx) = flume::bounded::<WriteCommand>(8);
                 let in_tx = Arc::new(in_tx);
                 let in_rx = Arc::new(in_rx);
                 let in_rx_ = in_rx.clone();
-                let pinned_port_ = pinned_port.clone();
+                let control_ = control.clone();
+                let cancel = CancellationToken::new();
+                let cancel_ = cancel.clone();
                 let in_handle = tokio::spawn(async move {
-                    for data in in_rx_.iter() {
-                        match pinned_port_.lock().await.write_all(&data).await {
-                            Ok(_) => {}
-                            Err(e) => {
-                                error!("error writing to serial port: {}", e);
-                            }
-                        }
+                    loop {
+                        let cmd = tokio::select! {
+                            r = in_rx_.recv_async() => match r {
+                                Ok(cmd) => cmd,
+                                Err(_) => break,
+                            },
+                            _ = cancel_.cancelled() => break,
+                        };
+                        write_to_port(&control_, cmd).await;
+                    }
+                    // write whatever is still queued before the port goes away
+                    for cmd in in_rx_.drain() {
+                        write_to_port(&control_, cmd).await;
                     }
                 });
+                let (modem, modem_handle) = modem::spawn_watcher(control.clone());
+                let mut bridge_handles = Vec::new();
+                let udp_peers = bridge::udp::Peers::default();
+                if let Some(s) = socket.clone() {
+                    bridge_handles.push(bridge::udp::spawn(
+                        s,
+                        udp_peers.clone(),
+                        outbound.subscribe(),
+                        in_tx.clone(),
+                    ));
+                }
+                if let Some(l) = tcp {
+                    bridge_handles.push(bridge::tcp::spawn(l, outbound.clone(), in_tx.clone()));
+                }
+                if let Some(l) = rfc2217 {
+                    bridge_handles.push(bridge::rfc2217::spawn(
+                        l,
+                        control.clone(),
+                        outbound.clone(),
+                        modem.clone(),
+                        in_tx.clone(),
+                    ));
+                }
                 // https://github.com/tokio-rs/tokio/discussions/3891
                 // https://hackernoon.com/pin-safety-understanding-pinning-in-rust-futures
                 // https://v0-1--tokio.netlify.app/docs/internals/net/
                 let managed_dev = ManagedSerialDevice {
-                    port: pinned_port,
-                    port_name: req.device.clone(),
-                    options: managed_options.clone(),
-                    udp: None,
+                    port_name: device.clone(),
+                    control,
+                    udp: socket,
+                    udp_peers,
+                    bridge_handles,
                     outbound_handle: out_handle,
                     inbound_handle: in_handle,
-                    outbound: Channel {
-                        rx: out_rx,
-                        tx: out_tx,
-                    },
+                    modem_handle,
+                    modem,
+                    cancel,
+                    outbound,
                     inbound: Channel {
                         rx: in_rx,
                         tx: in_tx,
@@ -271,9 +1372,19 @@ impl service::SerialService for SerialServer {
                 };
                 let mut response = api::Serial::default();
                 // https://github.com/hyperium/tonic/discussions/1094
+                managed_dev.control.status.set_state(api::DeviceState::Open);
                 let mut managed = self.managed.lock().await;
-                managed.insert(req.device.clone(), managed_dev);
-                response.device = req.device;
+                if let Some(timeout) = idle_timeout {
+                    idle::spawn_watcher(
+                        device.clone(),
+                        timeout,
+                        self.managed.clone(),
+                        managed_dev.cancel.clone(),
+                    );
+                }
+                response.status = Some(managed_dev.status());
+                managed.insert(device.clone(), managed_dev);
+                response.device = device;
                 response.managed = Some(managed_options);
                 Ok(tonic::Response::new(response))
             }
@@ -284,18 +1395,304 @@ impl service::SerialService for SerialServer {
         &self,
         req: tonic::Request<api::CloseRequest>,
     ) -> Result<tonic::Response<()>, tonic::Status> {
-        todo!()
+        let lease = lease::token(&req);
+        let req = req.into_inner();
+        let mut managed = self.managed.lock().await;
+        let dev = managed
+            .get(&req.device)
+            .ok_or_else(|| tonic::Status::not_found(format!("{} is not managed", req.device)))?;
+        if !req.force {
+            let left = dev.control.status.detach();
+            if left > 0 {
+                info!("{} detached, {} clients left", req.device, left);
+                return Ok(tonic::Response::new(()));
+            }
+        }
+        // closing it for everyone is up to the lease holder
+        if let Err(e) = dev.control.lease().check(lease.as_deref()) {
+            if !req.force {
+                dev.control.status.attach();
+            }
+            return Err(e);
+        }
+        let dev = managed.remove(&req.device);
+        // don't hold the map while flushing
+        drop(managed);
+        if let Some(dev) = dev {
+            dev.close().await;
+        }
+        Ok(tonic::Response::new(()))
     }
     async fn read(
         &self,
         req: tonic::Request<api::ReadRequest>,
     ) -> Result<tonic::Response<Self::ReadStream>, tonic::Status> {
-        todo!()
+        let req = req.into_inner();
+        let framing = req.lines.as_ref().map(LineFraming::from_api).transpose()?;
+        let subscription = self.with_device(&req.device, |d| d.subscribe()).await?;
+        let device = req.device;
+        let stream: Self::ReadStream = match framing {
+            Some(framing) => Box::pin(
+                framing::Lines::new(subscription, framing)
+                    .map(move |line| Ok(line.into_api(&device))),
+            ),
+            None => Box::pin(subscription.map(move |chunk| Ok(chunk.into_api(&device)))),
+        };
+        Ok(tonic::Response::new(stream))
     }
     async fn write(
         &self,
         req: tonic::Request<api::WriteRequest>,
     ) -> Result<tonic::Response<()>, tonic::Status> {
-        todo!()
+        let lease = lease::token(&req);
+        let req = req.into_inner();
+        let inbound = self.with_device(&req.device, |d| d.inbound()).await?;
+        write_acked(&inbound, &req.device, req.data, lease).await?;
+        Ok(tonic::Response::new(()))
+    }
+    async fn configure(
+        &self,
+        req: tonic::Request<api::ConfigureRequest>,
+    ) -> Result<tonic::Response<api::Serial>, tonic::Status> {
+        let lease = lease::token(&req);
+        let req = req.into_inner();
+        let options = req
+            .options
+            .ok_or_else(|| tonic::Status::invalid_argument("options must be specified"))?;
+        let control = self.with_device(&req.device, |d| d.control()).await?;
+        control.lease().check(lease.as_deref())?;
+        control
+            .configure(&options)
+            .await
+            .map_err(sp_error_2_status)?;
+        Ok(tonic::Response::new(api::Serial {
+            device: req.device,
+            managed: Some(control.managed_options()),
+            ..Default::default()
+        }))
+    }
+    async fn set_modem_lines(
+        &self,
+        req: tonic::Request<api::SetModemLinesRequest>,
+    ) -> Result<tonic::Response<api::ModemLines>, tonic::Status> {
+        let lease = lease::token(&req);
+        let req = req.into_inner();
+        let control = self.with_device(&req.device, |d| d.control()).await?;
+        control.lease().check(lease.as_deref())?;
+        control
+            .set_modem_lines(req.dtr, req.rts)
+            .await
+            .map_err(sp_error_2_status)?;
+        let lines = control.modem_lines().await.map_err(sp_error_2_status)?;
+        Ok(tonic::Response::new(lines))
+    }
+    async fn get_modem_lines(
+        &self,
+        req: tonic::Request<api::ModemLinesRequest>,
+    ) -> Result<tonic::Response<api::ModemLines>, tonic::Status> {
+        let req = req.into_inner();
+        let control = self.with_device(&req.device, |d| d.control()).await?;
+        let lines = control.modem_lines().await.map_err(sp_error_2_status)?;
+        Ok(tonic::Response::new(lines))
+    }
+    async fn r#break(
+        &self,
+        req: tonic::Request<api::BreakRequest>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let lease = lease::token(&req);
+        let req = req.into_inner();
+        let control = self.with_device(&req.device, |d| d.control()).await?;
+        control.lease().check(lease.as_deref())?;
+        control.break_request(&req.duration, req.on).await?;
+        Ok(tonic::Response::new(()))
+    }
+    async fn watch_modem_lines(
+        &self,
+        req: tonic::Request<api::ModemLinesRequest>,
+    ) -> Result<tonic::Response<Self::WatchModemLinesStream>, tonic::Status> {
+        let req = req.into_inner();
+        let modem = self.with_device(&req.device, |d| d.modem_watcher()).await?;
+        let (lines, events) = modem.watch().await.map_err(sp_error_2_status)?;
+        let first = modem::event(api::ModemLines::default(), lines);
+        let device = req.device;
+        let events = BroadcastStream::new(events).filter_map(move |e| match e {
+            Ok(e) => Some(Ok(e)),
+            Err(BroadcastStreamRecvError::Lagged(n)) => {
+                warn!("modem line watcher of {} missed {} events", device, n);
+                None
+            }
+        });
+        let stream = tokio_stream::once(Ok(first)).chain(events);
+        Ok(tonic::Response::new(Box::pin(stream)))
+    }
+    async fn watch_devices(
+        &self,
+        _req: tonic::Request<()>,
+    ) -> Result<tonic::Response<Self::WatchDevicesStream>, tonic::Status> {
+        let (mut ports, events) = self
+            .devices
+            .watch()
+            .await
+            .map_err(|e| tonic::Status::internal(e.description))?;
+        // opened or closed since they were listed
+        hotplug::annotate(&mut ports, &*self.managed.lock().await);
+        let present: Vec<_> = ports
+            .into_values()
+            .map(|serial| Ok(hotplug::event(api::DeviceEventType::Added, serial)))
+            .collect();
+        let events = BroadcastStream::new(events).filter_map(|e| match e {
+            Ok(e) => Some(Ok(e)),
+            Err(BroadcastStreamRecvError::Lagged(n)) => {
+                warn!("device watcher missed {} events", n);
+                None
+            }
+        });
+        let stream = UntilShutdown {
+            stream: Box::pin(tokio_stream::iter(present).chain(events)),
+            shutdown: Box::pin(self.shutdown.clone().cancelled_owned()),
+        };
+        Ok(tonic::Response::new(Box::pin(stream)))
+    }
+    async fn status(
+        &self,
+        req: tonic::Request<api::StatusRequest>,
+    ) -> Result<tonic::Response<api::DeviceStatus>, tonic::Status> {
+        let req = req.into_inner();
+        let status = self.with_device(&req.device, |d| d.status()).await?;
+        Ok(tonic::Response::new(status))
+    }
+    async fn stats(
+        &self,
+        req: tonic::Request<api::StatsRequest>,
+    ) -> Result<tonic::Response<api::DeviceStats>, tonic::Status> {
+        let req = req.into_inner();
+        let stats = self.with_device(&req.device, |d| d.stats()).await?;
+        Ok(tonic::Response::new(stats))
+    }
+    async fn session(
+        &self,
+        req: tonic::Request<tonic::Streaming<api::ClientMessage>>,
+    ) -> Result<tonic::Response<Self::SessionStream>, tonic::Status> {
+        let lease = lease::token(&req);
+        let mut incoming = req.into_inner();
+        let first = incoming
+            .message()
+            .await?
+            .ok_or_else(|| tonic::Status::invalid_argument("no message to attach the session"))?;
+        let Some(api::client_message::Command::Attach(attach)) = first.command else {
+            return Err(tonic::Status::invalid_argument(
+                "the first message must attach the session to a device",
+            ));
+        };
+        let attached = self
+            .with_device(&attach.device, |d| session::Attached {
+                device: attach.device.clone(),
+                subscription: d.subscribe(),
+                inbound: d.inbound(),
+                control: d.control(),
+                modem_events: attach.modem_lines.then(|| d.modem_watcher().subscribe()),
+                lease: lease.clone(),
+            })
+            .await?;
+        if let Some(token) = &lease {
+            attached.control.lease().bind(token)?;
+        }
+        let rx = session::spawn(attached, first.id, incoming);
+        Ok(tonic::Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+    async fn acquire(
+        &self,
+        req: tonic::Request<api::AcquireRequest>,
+    ) -> Result<tonic::Response<api::Lease>, tonic::Status> {
+        let token = lease::token(&req);
+        let req = req.into_inner();
+        let control = self.with_device(&req.device, |d| d.control()).await?;
+        let ttl = api_positive_duration_2_std(&req.ttl, "ttl")?;
+        let lease = control
+            .lease()
+            .acquire(&req.holder, ttl, token.as_deref())?;
+        Ok(tonic::Response::new(lease))
+    }
+    async fn release(
+        &self,
+        req: tonic::Request<api::ReleaseRequest>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let token = lease::token(&req);
+        let req = req.into_inner();
+        let control = self.with_device(&req.device, |d| d.control()).await?;
+        control.lease().release(token.as_deref())?;
+        Ok(tonic::Response::new(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A subscriber with room for 2 chunks that doesn't read while 3 are published
+    async fn lagging(policy: api::OverflowPolicy) -> (Arc<Subscribers>, Subscription) {
+        let subscribers = Arc::new(Subscribers::new(2, policy));
+        let subscription = subscribers.subscribe();
+        for data in [&b"a"[..], b"bb", b"ccc"] {
+            subscribers.publish(data).await;
+        }
+        (subscribers, subscription)
+    }
+
+    /// What's been queued for the subscription, as (seq, data)
+    async fn received(
+        subscribers: &Subscribers,
+        subscription: Subscription,
+    ) -> Vec<(u64, Vec<u8>)> {
+        // ends the subscription once it's drained
+        subscribers.clear();
+        subscription.map(|c| (c.seq, c.data)).collect().await
+    }
+
+    #[tokio::test]
+    async fn drop_oldest() {
+        let (subscribers, subscription) = lagging(api::OverflowPolicy::DropOldest).await;
+        assert_eq!(
+            received(&subscribers, subscription).await,
+            vec![(1, b"bb".to_vec()), (2, b"ccc".to_vec())]
+        );
+        assert_eq!(subscribers.dropped_chunks(), 1);
+        assert_eq!(subscribers.dropped_bytes(), 1);
+    }
+
+    #[tokio::test]
+    async fn drop_newest() {
+        let (subscribers, subscription) = lagging(api::OverflowPolicy::DropNewest).await;
+        assert_eq!(
+            received(&subscribers, subscription).await,
+            vec![(0, b"a".to_vec()), (1, b"bb".to_vec())]
+        );
+        assert_eq!(subscribers.dropped_chunks(), 1);
+        assert_eq!(subscribers.dropped_bytes(), 3);
+    }
+
+    #[tokio::test]
+    async fn block() {
+        let subscribers = Arc::new(Subscribers::new(2, api::OverflowPolicy::Block));
+        let mut subscription = subscribers.subscribe();
+        subscribers.publish(b"a").await;
+        subscribers.publish(b"bb").await;
+        let publishing = tokio::spawn({
+            let subscribers = subscribers.clone();
+            async move { subscribers.publish(b"ccc").await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!publishing.is_finished(), "publishing to a full subscriber");
+        assert_eq!(
+            subscription.next().await.map(|c| c.data),
+            Some(b"a".to_vec())
+        );
+        publishing.await.unwrap();
+        assert_eq!(
+            received(&subscribers, subscription).await,
+            vec![(1, b"bb".to_vec()), (2, b"ccc".to_vec())]
+        );
+        assert_eq!(subscribers.dropped_chunks(), 0);
+        assert_eq!(subscribers.dropped_bytes(), 0);
     }
 }
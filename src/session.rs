@@ -0,0 +1,176 @@
+//! The Session RPC: a single bidirectional stream to write to, control and read a managed device
+use crate::serial::api::out as api;
+use crate::server::{sp_error_2_status, write_acked, PortControl, Subscription, WriteCommand};
+use api::client_message::Command;
+use api::server_message::Message;
+use flume::Sender;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::StreamExt;
+use tracing::debug;
+
+/// How many messages could be queued for the client
+const SESSION_CAPACITY: usize = 16;
+
+type Outgoing = mpsc::Sender<Result<api::ServerMessage, tonic::Status>>;
+
+/// What a session needs of the device it's attached to
+pub struct Attached {
+    pub device: String,
+    pub subscription: Subscription,
+    pub inbound: Arc<Sender<WriteCommand>>,
+    pub control: PortControl,
+    /// only if the client asked for them
+    pub modem_events: Option<broadcast::Receiver<api::ModemLinesEvent>>,
+    /// the token of the lease held for as long as the session lives, if any
+    pub lease: Option<String>,
+}
+
+/// Run the commands of the client and stream the device back to it.
+/// `attach_id` is the id of the first message, the one that attached the session.
+pub fn spawn(
+    attached: Attached,
+    attach_id: u64,
+    incoming: tonic::Streaming<api::ClientMessage>,
+) -> mpsc::Receiver<Result<api::ServerMessage, tonic::Status>> {
+    let (tx, rx) = mpsc::channel(SESSION_CAPACITY);
+    let Attached {
+        device,
+        mut subscription,
+        inbound,
+        control,
+        mut modem_events,
+        lease,
+    } = attached;
+    // the commands are run in order, without holding back the output in the meantime
+    let commands = tokio::spawn(run_commands(
+        incoming,
+        device.clone(),
+        inbound,
+        control.clone(),
+        lease.clone(),
+        tx.clone(),
+    ));
+    tokio::spawn(async move {
+        let mut state = control.status().subscribe();
+        let first = [
+            Message::Ack(ack(attach_id, Ok(()))),
+            Message::Status(control.device_status(&device)),
+        ];
+        for message in first {
+            if send(&tx, message).await.is_err() {
+                break;
+            }
+        }
+        while !tx.is_closed() {
+            let message = tokio::select! {
+                chunk = subscription.next() => match chunk {
+                    Some(chunk) => Message::Data(chunk.into_api(&device)),
+                    // the device is closed
+                    None => break,
+                },
+                Some(e) = next_modem_event(&mut modem_events) => Message::ModemLines(e),
+                r = state.changed() => match r {
+                    Ok(()) => Message::Status(control.device_status(&device)),
+                    Err(_) => break,
+                },
+                // the client has gone away
+                _ = tx.closed() => break,
+            };
+            // the client has gone away
+            if send(&tx, message).await.is_err() {
+                break;
+            }
+        }
+        commands.abort();
+        if let Some(token) = &lease {
+            control.lease().unbind(token);
+        }
+    });
+    rx
+}
+
+async fn run_commands(
+    mut incoming: tonic::Streaming<api::ClientMessage>,
+    device: String,
+    inbound: Arc<Sender<WriteCommand>>,
+    control: PortControl,
+    lease: Option<String>,
+    tx: Outgoing,
+) {
+    loop {
+        let msg = match incoming.message().await {
+            Ok(Some(msg)) => msg,
+            // the client is done sending, it could still be reading
+            Ok(None) => break,
+            Err(e) => {
+                debug!("session on {} ended: {}", device, e);
+                break;
+            }
+        };
+        let r = match msg.command {
+            Some(Command::Data(data)) => write_acked(&inbound, &device, data, lease.clone()).await,
+            Some(Command::ModemLines(c)) => match control.lease().check(lease.as_deref()) {
+                Ok(()) => control
+                    .set_modem_lines(c.dtr, c.rts)
+                    .await
+                    .map_err(sp_error_2_status),
+                Err(e) => Err(e),
+            },
+            Some(Command::Break(c)) => match control.lease().check(lease.as_deref()) {
+                Ok(()) => control.break_request(&c.duration, c.on).await,
+                Err(e) => Err(e),
+            },
+            Some(Command::Configure(options)) => match control.lease().check(lease.as_deref()) {
+                Ok(()) => control.configure(&options).await.map_err(sp_error_2_status),
+                Err(e) => Err(e),
+            },
+            Some(Command::Attach(_)) => Err(tonic::Status::failed_precondition(
+                "the session is already attached",
+            )),
+            None => Err(tonic::Status::invalid_argument("empty message")),
+        };
+        if send(&tx, Message::Ack(ack(msg.id, r))).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Never ready if the client didn't ask for the modem lines
+async fn next_modem_event(
+    events: &mut Option<broadcast::Receiver<api::ModemLinesEvent>>,
+) -> Option<api::ModemLinesEvent> {
+    let Some(rx) = events else {
+        return std::future::pending().await;
+    };
+    loop {
+        match rx.recv().await {
+            Ok(e) => return Some(e),
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            // the device is closing, the subscription would end the session
+            Err(broadcast::error::RecvError::Closed) => return std::future::pending().await,
+        }
+    }
+}
+
+async fn send(tx: &Outgoing, message: Message) -> Result<(), ()> {
+    let message = api::ServerMessage {
+        message: Some(message),
+    };
+    tx.send(Ok(message)).await.map_err(|_| ())
+}
+
+fn ack(id: u64, r: Result<(), tonic::Status>) -> api::Ack {
+    match r {
+        Ok(()) => api::Ack {
+            id,
+            code: tonic::Code::Ok as i32,
+            message: String::new(),
+        },
+        Err(status) => api::Ack {
+            id,
+            code: status.code() as i32,
+            message: status.message().to_string(),
+        },
+    }
+}
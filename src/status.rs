@@ -0,0 +1,181 @@
+use crate::serial::api::out as api;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Instant, SystemTime};
+use tokio::sync::watch;
+use tracing::debug;
+
+/// What's going on with a managed device, for the Status, Stats and List RPCs
+pub struct DeviceStatus {
+    opened_at: SystemTime,
+    started: Instant,
+    state: parking_lot::Mutex<State>,
+    /// notified whenever the state changes
+    state_changes: watch::Sender<api::DeviceState>,
+    read: Counters,
+    written: Counters,
+    /// the Open calls it's attached to, less the Close calls
+    clients: AtomicU32,
+}
+
+struct State {
+    state: api::DeviceState,
+    last_error: Option<(String, SystemTime)>,
+}
+
+/// One direction of the traffic
+#[derive(Default)]
+struct Counters {
+    bytes: AtomicU64,
+    chunks: AtomicU64,
+    errors: AtomicU64,
+    rate: parking_lot::Mutex<Rate>,
+}
+
+/// Bytes per second, over the last complete second
+#[derive(Default)]
+struct Rate {
+    /// seconds since the device was opened
+    second: u64,
+    current: u64,
+    previous: u64,
+}
+
+impl Rate {
+    fn roll(&mut self, now: u64) {
+        if now != self.second {
+            self.previous = if now == self.second + 1 {
+                self.current
+            } else {
+                0
+            };
+            self.current = 0;
+            self.second = now;
+        }
+    }
+    fn add(&mut self, now: u64, n: u64) {
+        self.roll(now);
+        self.current += n;
+    }
+    fn get(&mut self, now: u64) -> u64 {
+        self.roll(now);
+        self.previous
+    }
+}
+
+impl Counters {
+    fn add(&self, now: u64, n: usize) {
+        self.bytes.fetch_add(n as u64, Ordering::Relaxed);
+        self.chunks.fetch_add(1, Ordering::Relaxed);
+        self.rate.lock().add(now, n as u64);
+    }
+}
+
+impl Default for DeviceStatus {
+    fn default() -> Self {
+        DeviceStatus {
+            opened_at: SystemTime::now(),
+            started: Instant::now(),
+            state: parking_lot::Mutex::new(State {
+                state: api::DeviceState::Opening,
+                last_error: None,
+            }),
+            state_changes: watch::channel(api::DeviceState::Opening).0,
+            read: Counters::default(),
+            written: Counters::default(),
+            // the Open that created it
+            clients: AtomicU32::new(1),
+        }
+    }
+}
+
+impl DeviceStatus {
+    pub fn set_state(&self, state: api::DeviceState) {
+        let mut s = self.state.lock();
+        if s.state != state {
+            debug!("{:?} -> {:?}", s.state, state);
+            s.state = state;
+            self.state_changes.send_replace(state);
+        }
+    }
+    pub fn subscribe(&self) -> watch::Receiver<api::DeviceState> {
+        self.state_changes.subscribe()
+    }
+    fn error(&self, e: impl Display) {
+        self.state.lock().last_error = Some((e.to_string(), SystemTime::now()));
+    }
+    /// Record the read error that brought the device into `state`
+    pub fn read_error(&self, state: api::DeviceState, e: impl Display) {
+        self.read.errors.fetch_add(1, Ordering::Relaxed);
+        self.error(e);
+        self.set_state(state);
+    }
+    /// Record a write error, the device is still usable
+    pub fn write_error(&self, e: impl Display) {
+        self.written.errors.fetch_add(1, Ordering::Relaxed);
+        self.error(e);
+    }
+    pub fn attach(&self) -> u32 {
+        self.clients.fetch_add(1, Ordering::Relaxed) + 1
+    }
+    /// How many clients are left
+    pub fn detach(&self) -> u32 {
+        let prev = self
+            .clients
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                Some(n.saturating_sub(1))
+            })
+            .unwrap();
+        prev.saturating_sub(1)
+    }
+    pub fn add_read(&self, n: usize) {
+        self.read.add(self.now(), n);
+    }
+    pub fn add_written(&self, n: usize) {
+        self.written.add(self.now(), n);
+    }
+    fn now(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+    /// The lease is held by [crate::lease::LeaseSlot]
+    pub fn to_api(&self, device: &str, lease_holder: Option<String>) -> api::DeviceStatus {
+        let s = self.state.lock();
+        let (last_error, last_error_time) = match &s.last_error {
+            Some((e, t)) => (e.clone(), Some((*t).into())),
+            None => (String::new(), None),
+        };
+        api::DeviceStatus {
+            device: device.to_string(),
+            state: s.state as i32,
+            last_error,
+            last_error_time,
+            opened_at: Some(self.opened_at.into()),
+            bytes_read: self.read.bytes.load(Ordering::Relaxed),
+            bytes_written: self.written.bytes.load(Ordering::Relaxed),
+            lease_holder: lease_holder.unwrap_or_default(),
+            clients: self.clients.load(Ordering::Relaxed),
+        }
+    }
+    /// The dropped chunks and bytes are counted by [crate::server::Subscribers]
+    pub fn to_api_stats(
+        &self,
+        device: &str,
+        dropped_chunks: u64,
+        dropped_bytes: u64,
+    ) -> api::DeviceStats {
+        let now = self.now();
+        api::DeviceStats {
+            device: device.to_string(),
+            bytes_read: self.read.bytes.load(Ordering::Relaxed),
+            chunks_read: self.read.chunks.load(Ordering::Relaxed),
+            bytes_written: self.written.bytes.load(Ordering::Relaxed),
+            chunks_written: self.written.chunks.load(Ordering::Relaxed),
+            read_errors: self.read.errors.load(Ordering::Relaxed),
+            write_errors: self.written.errors.load(Ordering::Relaxed),
+            dropped_chunks,
+            dropped_bytes,
+            rx_rate: self.read.rate.lock().get(now),
+            tx_rate: self.written.rate.lock().get(now),
+        }
+    }
+}
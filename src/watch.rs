@@ -0,0 +1,121 @@
+//! Polling some state only while it's watched and broadcasting how it changes,
+//! e.g. the modem lines of a port or the available ports
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+/// How many events a slow receiver could lag behind before missing some
+const EVENT_CAPACITY: usize = 64;
+
+type ReadFuture<S> = Pin<Box<dyn Future<Output = Result<S, serialport::Error>> + Send>>;
+type Read<S> = Arc<dyn Fn() -> ReadFuture<S> + Send + Sync>;
+
+/// Reads a state of type `S` every so often and broadcasts the events `E` telling how it changed
+/// since the previous reading. Nothing is read while there are no receivers,
+/// e.g. a port is left alone most of the time.
+pub struct Watcher<S, E> {
+    read: Read<S>,
+    events: broadcast::Sender<E>,
+    /// what the next reading is compared with, `None` while nobody is watching.
+    /// Held while reading, so that [Watcher::watch] can't hand out a state that's already stale.
+    last: Arc<Mutex<Option<S>>>,
+}
+
+impl<S, E> Clone for Watcher<S, E> {
+    fn clone(&self) -> Self {
+        Watcher {
+            read: self.read.clone(),
+            events: self.events.clone(),
+            last: self.last.clone(),
+        }
+    }
+}
+
+impl<S, E> Watcher<S, E>
+where
+    S: Clone + Send + 'static,
+    E: Clone + Send + 'static,
+{
+    /// Call `read` every `interval` and broadcast `diff(previous, current)`.
+    /// `what` names the state in the logs.
+    pub fn spawn<R, F, D>(
+        what: &'static str,
+        interval: Duration,
+        read: R,
+        diff: D,
+    ) -> (Self, JoinHandle<()>)
+    where
+        R: Fn() -> F + Send + Sync + 'static,
+        F: Future<Output = Result<S, serialport::Error>> + Send + 'static,
+        D: Fn(&S, &S) -> Vec<E> + Send + 'static,
+    {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let watcher = Watcher {
+            read: Arc::new(move || Box::pin(read()) as ReadFuture<S>),
+            events,
+            last: Default::default(),
+        };
+        let w = watcher.clone();
+        let handle = tokio::spawn(async move {
+            let mut failing = false;
+            let mut interval = tokio::time::interval(interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                interval.tick().await;
+                let mut last = w.last.lock().await;
+                if w.events.receiver_count() == 0 {
+                    *last = None;
+                    continue;
+                }
+                let state = match (w.read)().await {
+                    Ok(state) => {
+                        if failing {
+                            debug!("{} can be read again", what);
+                            failing = false;
+                        }
+                        state
+                    }
+                    Err(e) => {
+                        if !failing {
+                            warn!("error reading {}: {}", what, e);
+                            failing = true;
+                        }
+                        continue;
+                    }
+                };
+                if let Some(prev) = &*last {
+                    for e in diff(prev, &state) {
+                        let _ = w.events.send(e);
+                    }
+                }
+                *last = Some(state);
+            }
+        });
+        (watcher, handle)
+    }
+
+    /// Subscribe to the events, along with the state they start from.
+    /// No change could be missed in between, as the next reading is compared with that very state.
+    pub async fn watch(&self) -> Result<(S, broadcast::Receiver<E>), serialport::Error> {
+        let mut last = self.last.lock().await;
+        let events = self.events.subscribe();
+        let state = match &*last {
+            Some(state) => state.clone(),
+            None => {
+                let state = (self.read)().await?;
+                *last = Some(state.clone());
+                state
+            }
+        };
+        Ok((state, events))
+    }
+
+    /// Subscribe to the events alone
+    pub fn subscribe(&self) -> broadcast::Receiver<E> {
+        self.events.subscribe()
+    }
+}